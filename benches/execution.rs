@@ -0,0 +1,72 @@
+// compare executing bytes with executing pre-decoded programs
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use evoby::{Genome, Interpreter, Linking, Machine};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+// a program that spends its time in loops, calls and values
+fn loops() -> Genome {
+    Genome::assemble(&["
+        value r3 200
+        repeat r3
+        call @0x0000_0001
+        block @0x0000_0001
+        value r2 50
+        repeat r2
+        call @0x0000_0002
+        block @0x0000_0002
+        value r1 12345
+        add r0 r1
+        if r0
+        xor r0 r2
+        "])
+    .unwrap()
+}
+
+// random genomes, like the ones at the start of an evolutionary run
+fn random_genomes(count: usize) -> Vec<Genome> {
+    let mut rng = SmallRng::seed_from_u64(0);
+    (0..count)
+        .map(|_| {
+            let bytes = (0..256).map(|_| rng.gen()).collect::<Vec<u8>>();
+            Genome::from_bytes(&bytes)
+        })
+        .collect()
+}
+
+fn bench_interpreters(c: &mut Criterion) {
+    let mut group = c.benchmark_group("execution");
+    let loops = loops();
+    let random = random_genomes(100);
+    for interpreter in [Interpreter::Bytes, Interpreter::Compiled] {
+        let name = format!("{:?}", interpreter);
+        let mut machine = Machine::new()
+            .fuel(100_000)
+            .linking(Linking::Deterministic)
+            .interpreter(interpreter);
+        let program = machine.link(&loops);
+        group.bench_with_input(BenchmarkId::new("loops", &name), &program, |b, program| {
+            b.iter(|| machine.run_program(black_box(program), &[1, 2]))
+        });
+        let programs = random
+            .iter()
+            .map(|genome| machine.link(genome))
+            .collect::<Vec<_>>();
+        group.bench_with_input(
+            BenchmarkId::new("random", &name),
+            &programs,
+            |b, programs| {
+                b.iter(|| {
+                    for program in programs {
+                        machine.run_program(black_box(program), &[1, 2]);
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_interpreters);
+criterion_main!(benches);
@@ -0,0 +1,515 @@
+// a textual assembly language for strands
+//
+// each line contains a single instruction, or a block header that starts a
+// new block. a block header can have a pattern that identifies the block.
+// comments start with ';'.
+//
+//     inc r0
+//     call @0x5a5a_1234   ; call with the pattern in pattern bytes before it
+//     call @0x5a5a_0000~0x0000_ffff ; and with wildcards in pattern bytes before that
+//     block @0x5a5a_1234
+//     value r2 42         ; value with the value in pattern bytes before it
+//     add r0 r2
+//
+// call and value without operands are assembled as a single byte, so that
+// they take their pattern from whatever bytes come before them.
+
+use std::fmt;
+
+use crate::blockpattern::BlockPattern;
+use crate::instruction::{BlockRef, Instruction, RegisterId};
+use crate::structure::StrandBuilder;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    /// line and column are 1-based
+    pub line: usize,
+    pub column: usize,
+    pub kind: AssembleErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssembleErrorKind {
+    UnknownMnemonic(String),
+    InvalidRegister(String),
+    InvalidNumber(String),
+    InvalidPattern(String),
+    OutOfRange(String),
+    MissingOperand,
+    UnexpectedOperand(String),
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match &self.kind {
+            AssembleErrorKind::UnknownMnemonic(text) => write!(f, "unknown mnemonic '{}'", text),
+            AssembleErrorKind::InvalidRegister(text) => write!(f, "invalid register '{}'", text),
+            AssembleErrorKind::InvalidNumber(text) => write!(f, "invalid number '{}'", text),
+            AssembleErrorKind::InvalidPattern(text) => write!(f, "invalid pattern '{}'", text),
+            AssembleErrorKind::OutOfRange(text) => write!(f, "number out of range '{}'", text),
+            AssembleErrorKind::MissingOperand => write!(f, "missing operand"),
+            AssembleErrorKind::UnexpectedOperand(text) => {
+                write!(f, "unexpected operand '{}'", text)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+// assemble the source of a single strand into bytes
+pub(crate) fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    let mut builder = StrandBuilder::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let mut tokens = Tokens::new(line, line_index + 1);
+        let mnemonic = match tokens.next() {
+            Some(mnemonic) => mnemonic,
+            None => continue,
+        };
+        let built = match mnemonic.text {
+            "block" => match tokens.next() {
+                Some(token) => Ok(builder.block(tokens.pattern(token)?)),
+                None => Ok(builder.empty_block()),
+            },
+            "call" => match tokens.next() {
+                Some(token) => builder
+                    .instruction(Instruction::Call(BlockRef::Pattern(tokens.pattern(token)?))),
+                None => builder.bare(Instruction::Call(BlockRef::Pattern(BlockPattern::new(0)))),
+            },
+            "value" => match tokens.next() {
+                Some(token) => {
+                    let register = tokens.register(Some(token))?;
+                    let value = tokens.number(None, 0x3FFF_FFFF)?;
+                    builder.instruction(Instruction::Value(register, value))
+                }
+                None => builder.bare(Instruction::Value(RegisterId(0), 0)),
+            },
+            _ => {
+                let instruction = tokens.instruction(mnemonic)?;
+                builder.instruction(instruction)
+            }
+        };
+        // the operands are checked while they are parsed, so every
+        // assembled instruction has an encoding
+        builder = built.expect("assembled instructions are encodable");
+        tokens.end()?;
+    }
+    Ok(builder.build())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+struct Tokens<'a> {
+    line: usize,
+    end_column: usize,
+    tokens: Vec<Token<'a>>,
+    position: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(text: &'a str, line: usize) -> Tokens<'a> {
+        // everything after a ';' is a comment
+        let text = match text.find(';') {
+            Some(index) => &text[..index],
+            None => text,
+        };
+        let mut tokens = Vec::new();
+        let mut start = None;
+        for (index, c) in text.char_indices().chain([(text.len(), ' ')]) {
+            let separator = c.is_whitespace() || c == ',';
+            match (start, separator) {
+                (None, false) => start = Some(index),
+                (Some(token_start), true) => {
+                    tokens.push(Token {
+                        text: &text[token_start..index],
+                        column: token_start + 1,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        Tokens {
+            line,
+            end_column: text.len() + 1,
+            tokens,
+            position: 0,
+        }
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.tokens.get(self.position).copied();
+        if token.is_some() {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn error(&self, column: usize, kind: AssembleErrorKind) -> AssembleError {
+        AssembleError {
+            line: self.line,
+            column,
+            kind,
+        }
+    }
+
+    fn next_operand(&mut self) -> Result<Token<'a>, AssembleError> {
+        self.next()
+            .ok_or_else(|| self.error(self.end_column, AssembleErrorKind::MissingOperand))
+    }
+
+    fn end(&mut self) -> Result<(), AssembleError> {
+        match self.next() {
+            Some(token) => Err(self.error(
+                token.column,
+                AssembleErrorKind::UnexpectedOperand(token.text.to_string()),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn register(&mut self, token: Option<Token<'a>>) -> Result<RegisterId, AssembleError> {
+        let token = match token {
+            Some(token) => token,
+            None => self.next_operand()?,
+        };
+        match token.text {
+            "r0" => Ok(RegisterId(0)),
+            "r1" => Ok(RegisterId(1)),
+            "r2" => Ok(RegisterId(2)),
+            "r3" => Ok(RegisterId(3)),
+            text => Err(self.error(
+                token.column,
+                AssembleErrorKind::InvalidRegister(text.to_string()),
+            )),
+        }
+    }
+
+    fn number(&mut self, token: Option<Token<'a>>, max: u32) -> Result<u32, AssembleError> {
+        let token = match token {
+            Some(token) => token,
+            None => self.next_operand()?,
+        };
+        let number = parse_number(token.text).ok_or_else(|| {
+            self.error(
+                token.column,
+                AssembleErrorKind::InvalidNumber(token.text.to_string()),
+            )
+        })?;
+        if number > max as u64 {
+            return Err(self.error(
+                token.column,
+                AssembleErrorKind::OutOfRange(token.text.to_string()),
+            ));
+        }
+        Ok(number as u32)
+    }
+
+    fn pattern(&self, token: Token<'a>) -> Result<BlockPattern, AssembleError> {
+        let invalid = || {
+            self.error(
+                token.column,
+                AssembleErrorKind::InvalidPattern(token.text.to_string()),
+            )
+        };
+        let out_of_range = || {
+            self.error(
+                token.column,
+                AssembleErrorKind::OutOfRange(token.text.to_string()),
+            )
+        };
+        let text = token.text.strip_prefix('@').ok_or_else(invalid)?;
+        // wildcards follow the pattern after a '~'
+        let (number, wildcards) = match text.split_once('~') {
+            Some((number, wildcards)) => (number, Some(wildcards)),
+            None => (text, None),
+        };
+        let pattern = parse_number(number).ok_or_else(invalid)?;
+        let pattern = u32::try_from(pattern).map_err(|_| out_of_range())?;
+        let wildcards = match wildcards {
+            Some(wildcards) => {
+                let wildcards = parse_number(wildcards).ok_or_else(invalid)?;
+                u32::try_from(wildcards).map_err(|_| out_of_range())?
+            }
+            None => 0,
+        };
+        Ok(BlockPattern::new(pattern).with_wildcards(wildcards))
+    }
+
+    fn instruction(&mut self, mnemonic: Token<'a>) -> Result<Instruction, AssembleError> {
+        let one =
+            |tokens: &mut Self, f: fn(RegisterId) -> Instruction| Ok(f(tokens.register(None)?));
+        let two = |tokens: &mut Self, f: fn(RegisterId, RegisterId) -> Instruction| {
+            let r0 = tokens.register(None)?;
+            let r1 = tokens.register(None)?;
+            Ok(f(r0, r1))
+        };
+        match mnemonic.text {
+            "return" => Ok(Instruction::Return),
+            "if" => one(self, Instruction::If),
+            "repeat" => one(self, Instruction::Repeat),
+            "not" => one(self, Instruction::Not),
+            "push" => one(self, Instruction::Push),
+            "pop" => one(self, Instruction::Pop),
+            "inc" => one(self, Instruction::Inc),
+            "dec" => one(self, Instruction::Dec),
+            "store" => two(self, Instruction::Store),
+            "load" => two(self, Instruction::Load),
+            "add" => two(self, Instruction::Add),
+            "sub" => two(self, Instruction::Sub),
+            "mul" => two(self, Instruction::Mul),
+            "div" => two(self, Instruction::Div),
+            "eq" => two(self, Instruction::Eq),
+            "gt" => two(self, Instruction::Gt),
+            "and" => two(self, Instruction::And),
+            "or" => two(self, Instruction::Or),
+            "xor" => two(self, Instruction::Xor),
+            "unknown0" => two(self, Instruction::Unknown0),
+            "unknown1" => two(self, Instruction::Unknown1),
+            "pattern" => {
+                let nibble = self.number(None, 0b1111)?;
+                Ok(Instruction::Pattern(nibble as u8))
+            }
+            text => Err(self.error(
+                mnemonic.column,
+                AssembleErrorKind::UnknownMnemonic(text.to_string()),
+            )),
+        }
+    }
+}
+
+// parse a decimal, hexadecimal (0x) or binary (0b) number, which
+// may contain underscores
+fn parse_number(text: &str) -> Option<u64> {
+    let text = text.replace('_', "");
+    let (digits, radix) = if let Some(digits) = text.strip_prefix("0x") {
+        (digits, 16)
+    } else if let Some(digits) = text.strip_prefix("0b") {
+        (digits, 2)
+    } else {
+        (text.as_str(), 10)
+    };
+    u64::from_str_radix(digits, radix).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> (usize, usize, AssembleErrorKind) {
+        let error = assemble(source).unwrap_err();
+        (error.line, error.column, error.kind)
+    }
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+            ; the entry block
+            inc r0
+            call @0x5a5a_1234
+            block @0x5a5a_1234
+            value r2 42
+            add r0, r2
+        ";
+        let expected = StrandBuilder::new()
+            .instruction(Instruction::Inc(RegisterId(0)))
+            .unwrap()
+            .instruction(Instruction::Call(BlockRef::Pattern(BlockPattern::new(
+                0x5a5a_1234,
+            ))))
+            .unwrap()
+            .block(BlockPattern::new(0x5a5a_1234))
+            .instruction(Instruction::Value(RegisterId(2), 42))
+            .unwrap()
+            .instruction(Instruction::Add(RegisterId(0), RegisterId(2)))
+            .unwrap()
+            .build();
+        assert_eq!(assemble(source), Ok(expected));
+    }
+
+    #[test]
+    fn test_assemble_all_mnemonics() {
+        let source = "
+            return
+            if r0
+            repeat r1
+            not r2
+            push r3
+            pop r0
+            inc r1
+            dec r2
+            store r3 r0
+            load r0 r1
+            add r1 r2
+            sub r2 r3
+            mul r3 r0
+            div r0 r1
+            eq r1 r2
+            gt r2 r3
+            and r3 r0
+            or r0 r1
+            xor r1 r2
+            unknown0 r2 r3
+            unknown1 r3 r0
+            pattern 0xa
+        ";
+        let bytes = assemble(source).unwrap();
+        assert_eq!(
+            bytes,
+            vec![
+                0x02, 0x04, 0x09, 0x0E, 0x13, 0x14, 0x19, 0x1E, 0x2C, 0x31, 0x46, 0x5B, 0x6C, 0x71,
+                0x86, 0x9B, 0xAC, 0xB1, 0xC6, 0xDB, 0xEC, 0xFA
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assemble_bare() {
+        let source = "
+            pattern 1
+            pattern 8
+            value
+            call
+        ";
+        assert_eq!(assemble(source), Ok(vec![0xF1, 0xF8, 0x03, 0x01]));
+    }
+
+    #[test]
+    fn test_assemble_blocks() {
+        // the first block is started implicitly
+        let source = "
+            block
+            inc r0
+            block
+            inc r1
+            block
+        ";
+        assert_eq!(assemble(source), Ok(vec![0x18, 0x00, 0x19, 0x00]));
+    }
+
+    #[test]
+    fn test_assemble_numbers() {
+        for (source, value) in [
+            ("value r0 42", 42),
+            ("value r0 0x2a", 42),
+            ("value r0 0b10_1010", 42),
+            ("value r0 1_000", 1000),
+        ] {
+            let expected = StrandBuilder::new()
+                .instruction(Instruction::Value(RegisterId(0), value))
+                .unwrap()
+                .build();
+            assert_eq!(assemble(source), Ok(expected), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_error_unknown_mnemonic() {
+        assert_eq!(
+            error("inc r0\n  jump r1"),
+            (2, 3, AssembleErrorKind::UnknownMnemonic("jump".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_error_invalid_register() {
+        assert_eq!(
+            error("add r0 r4"),
+            (1, 8, AssembleErrorKind::InvalidRegister("r4".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_error_missing_operand() {
+        assert_eq!(
+            error("add r0 ; comment"),
+            (1, 8, AssembleErrorKind::MissingOperand)
+        );
+        assert_eq!(error("value r0"), (1, 9, AssembleErrorKind::MissingOperand));
+    }
+
+    #[test]
+    fn test_error_unexpected_operand() {
+        assert_eq!(
+            error("return r0"),
+            (1, 8, AssembleErrorKind::UnexpectedOperand("r0".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_error_invalid_number() {
+        assert_eq!(
+            error("value r0 forty"),
+            (1, 10, AssembleErrorKind::InvalidNumber("forty".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_error_out_of_range() {
+        assert_eq!(
+            error("value r0 0x4000_0000"),
+            (
+                1,
+                10,
+                AssembleErrorKind::OutOfRange("0x4000_0000".to_string())
+            )
+        );
+        assert_eq!(
+            error("pattern 16"),
+            (1, 9, AssembleErrorKind::OutOfRange("16".to_string()))
+        );
+        assert_eq!(
+            error("call @0x1_0000_0000"),
+            (
+                1,
+                6,
+                AssembleErrorKind::OutOfRange("@0x1_0000_0000".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn test_error_invalid_pattern() {
+        assert_eq!(
+            error("block 0x1234"),
+            (
+                1,
+                7,
+                AssembleErrorKind::InvalidPattern("0x1234".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn test_assemble_wildcards() {
+        let bytes = assemble("call @0x5a5a_0000~0x0000_ffff").unwrap();
+        assert_eq!(
+            bytes,
+            [
+                &BlockPattern::new(0x0000_ffff).to_pattern_bytes()[..],
+                &BlockPattern::new(0x5a5a_0000).to_pattern_bytes()[..],
+                &[0x01],
+            ]
+            .concat()
+        );
+        assert_eq!(
+            error("call @0x1234~"),
+            (
+                1,
+                6,
+                AssembleErrorKind::InvalidPattern("@0x1234~".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn test_error_display() {
+        let error = assemble("\n  jump").unwrap_err();
+        assert_eq!(error.to_string(), "2:3: unknown mnemonic 'jump'");
+    }
+}
@@ -1,14 +1,30 @@
+use std::fmt;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub(crate) struct BlockId {
+pub struct BlockId {
     strand_id: usize,
     block_index: usize,
 }
 
 impl BlockId {
-    pub(crate) fn new(strand_id: usize, block_index: usize) -> BlockId {
+    pub fn new(strand_id: usize, block_index: usize) -> BlockId {
         BlockId {
             strand_id,
             block_index,
         }
     }
+
+    pub fn strand_id(&self) -> usize {
+        self.strand_id
+    }
+
+    pub fn block_index(&self) -> usize {
+        self.block_index
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.strand_id, self.block_index)
+    }
 }
@@ -1,35 +1,135 @@
-#[derive(Debug, PartialEq, Eq, Hash)]
-pub(crate) struct BlockPattern(u32);
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::instruction::Op;
+
+/// A 32 bit pattern that identifies a block, or the block a call looks for.
+///
+/// Patterns are decoded from four bytes. When there are fewer bytes, the
+/// pattern is padded with zeros, and only the bits actually decoded are
+/// present. A call can also have wildcards: bits it doesn't care about.
+/// Which of these bits count when matching depends on [`Patterns`].
+///
+/// Patterns are equal when their bits and wildcards are, however many of
+/// the bits were present.
+#[derive(Debug, Clone, Copy)]
+pub struct BlockPattern {
+    pattern: u32,
+    // the bits that were decoded from bytes
+    present: u32,
+    wildcards: u32,
+}
+
+impl PartialEq for BlockPattern {
+    fn eq(&self, other: &BlockPattern) -> bool {
+        (self.pattern, self.wildcards) == (other.pattern, other.wildcards)
+    }
+}
+
+impl Eq for BlockPattern {}
+
+impl Hash for BlockPattern {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.pattern, self.wildcards).hash(state);
+    }
+}
+
+/// Which bits of the patterns of calls and blocks count when matching them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Patterns {
+    /// All 32 bits count, including the zeros that pad short patterns.
+    #[default]
+    Fixed,
+    /// Only the bits present in both patterns count, so short blocks are
+    /// identified by the bytes they have, and short patterns before calls
+    /// are less specific. Patterns without bits in common don't match, so
+    /// neither does a pattern without any bits.
+    Variable,
+    /// Like variable patterns, but a call also reads a pattern before its
+    /// own pattern, with the bits set that it ignores.
+    Masked,
+}
+
+/// The direction in which a pattern is decoded: forward from the start of a
+/// block, or backward from a call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
 
 impl BlockPattern {
-    pub(crate) fn new(data: u32) -> BlockPattern {
-        BlockPattern(data)
-    }
-
-    pub(crate) fn decode_forward(data: &[u8], index: usize) -> BlockPattern {
-        let (first_byte, index) = Self::decode_byte_forward(data, index);
-        let (second_byte, index) = Self::decode_byte_forward(data, index);
-        let (third_byte, index) = Self::decode_byte_forward(data, index);
-        let (fourth_byte, _index) = Self::decode_byte_forward(data, index);
-        BlockPattern(
-            (first_byte as u32) << 24
-                | (second_byte as u32) << 16
-                | (third_byte as u32) << 8
-                | fourth_byte as u32,
-        )
-    }
-
-    pub(crate) fn decode_backward(data: &[u8], index: usize) -> BlockPattern {
-        let (fourth_byte, index) = Self::decode_byte_backward(data, index);
-        let (third_byte, index) = Self::decode_byte_backward(data, index);
-        let (second_byte, index) = Self::decode_byte_backward(data, index);
-        let (first_byte, _index) = Self::decode_byte_backward(data, index);
-        BlockPattern(
-            (first_byte as u32) << 24
-                | (second_byte as u32) << 16
-                | (third_byte as u32) << 8
-                | fourth_byte as u32,
-        )
+    /// A pattern with all 32 bits present and no wildcards.
+    pub fn new(data: u32) -> BlockPattern {
+        BlockPattern {
+            pattern: data,
+            present: u32::MAX,
+            wildcards: 0,
+        }
+    }
+
+    /// The same pattern, with only the given bits present.
+    pub fn with_present(self, present: u32) -> BlockPattern {
+        BlockPattern { present, ..self }
+    }
+
+    /// The same pattern, ignoring the given bits when matching.
+    pub fn with_wildcards(self, wildcards: u32) -> BlockPattern {
+        BlockPattern { wildcards, ..self }
+    }
+
+    pub fn decode_forward(data: &[u8], index: usize) -> BlockPattern {
+        let mut pattern = 0;
+        let mut present = 0;
+        let mut index = index;
+        // the first byte is the highest
+        for shift in [24, 16, 8, 0] {
+            let (byte, next) = Self::decode_byte_forward(data, index);
+            pattern |= (byte as u32) << shift;
+            if next > index {
+                present |= 0xFF << shift;
+            }
+            index = next;
+        }
+        BlockPattern::new(pattern).with_present(present)
+    }
+
+    pub fn decode_backward(data: &[u8], index: usize) -> BlockPattern {
+        Self::decode_backward_from(data, index).0
+    }
+
+    /// Decode the pattern of a call with wildcards: the pattern right
+    /// before index, and the wildcards in the pattern bytes right before
+    /// that. Without pattern bytes for the wildcards, there are none.
+    pub fn decode_backward_masked(data: &[u8], index: usize) -> BlockPattern {
+        let (pattern, index) = Self::decode_backward_from(data, index);
+        // only an explicit run of pattern bytes holds wildcards, not
+        // whatever instructions come before the pattern
+        let run = data[..index]
+            .iter()
+            .rev()
+            .take_while(|&&byte| is_pattern_byte(byte))
+            .count();
+        let (wildcards, _) = Self::decode_backward_from(&data[index - run..index], run);
+        pattern.with_wildcards(wildcards.pattern)
+    }
+
+    // decode backward from index, and return where the pattern starts
+    fn decode_backward_from(data: &[u8], index: usize) -> (BlockPattern, usize) {
+        // decoding from beyond the end of the data starts at the end
+        let mut index = index.min(data.len());
+        let mut pattern = 0;
+        let mut present = 0;
+        // the byte closest to index is the lowest
+        for shift in [0, 8, 16, 24] {
+            if index > 0 {
+                present |= 0xFF << shift;
+            }
+            let (byte, previous) = Self::decode_byte_backward(data, index);
+            pattern |= (byte as u32) << shift;
+            index = previous;
+        }
+        (BlockPattern::new(pattern).with_present(present), index)
     }
 
     fn decode_byte_forward(data: &[u8], index: usize) -> (u8, usize) {
@@ -93,17 +193,151 @@ impl BlockPattern {
         (byte, index)
     }
 
-    pub(crate) fn get(&self) -> u32 {
-        self.0
+    pub fn get(&self) -> u32 {
+        self.pattern
+    }
+
+    /// The bits that were decoded from bytes.
+    pub fn present(&self) -> u32 {
+        self.present
+    }
+
+    /// The amount of bits that were decoded from bytes: 0, 8, 16, 24 or 32.
+    pub fn length(&self) -> u32 {
+        self.present.count_ones()
+    }
+
+    /// The bits a call ignores when matching.
+    pub fn wildcards(&self) -> u32 {
+        self.wildcards
+    }
+
+    /// The bits of this pattern that count when matching.
+    pub fn mask(&self, patterns: Patterns) -> u32 {
+        match patterns {
+            Patterns::Fixed => u32::MAX,
+            Patterns::Variable => self.present,
+            Patterns::Masked => self.present & !self.wildcards,
+        }
+    }
+
+    /// Encode the pattern in as few bytes as possible, so that they decode
+    /// to the pattern in the given direction whatever bytes are around
+    /// them. The bytes are never zero, so they don't split a block.
+    ///
+    /// A byte of the pattern is encoded as itself when it can't be mistaken
+    /// for part of a pattern byte pair and doesn't change the flow of
+    /// execution, and as a pair otherwise. Pairs are pattern bytes where
+    /// possible, and otherwise use the reserved no-op `unknown0`. The bytes
+    /// encoded as themselves are still instructions, so when execution
+    /// passes through the pattern they change registers, memory or the
+    /// stack; only [`BlockPattern::to_pattern_bytes`] has no effect at all.
+    ///
+    /// The wildcards of a pattern decoded backward are encoded before it
+    /// as pattern bytes, as only those are read for wildcards.
+    pub fn encode(self, direction: Direction) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8);
+        match direction {
+            Direction::Forward => {
+                let mut after_raw = false;
+                for (i, byte) in self.pattern.to_be_bytes().into_iter().enumerate() {
+                    // the last byte is followed by whatever comes next
+                    let last = i == 3;
+                    if is_raw(byte) && !last {
+                        bytes.push(byte);
+                        after_raw = true;
+                        continue;
+                    }
+                    // a raw byte would pair with a pattern byte after it
+                    let high = if after_raw { NO_OP } else { PATTERN };
+                    bytes.extend([high | byte >> 4, PATTERN | byte & 0x0F]);
+                    after_raw = false;
+                }
+            }
+            Direction::Backward => {
+                if self.wildcards != 0 {
+                    bytes.extend(BlockPattern::new(self.wildcards).to_pattern_bytes());
+                }
+                let pattern = self.pattern.to_be_bytes();
+                for (i, &byte) in pattern.iter().enumerate() {
+                    // the first byte is preceded by whatever comes before
+                    let first = i == 0;
+                    if is_raw(byte) && !first {
+                        bytes.push(byte);
+                        continue;
+                    }
+                    // a raw byte would pair with a pattern byte before it
+                    let before_raw = pattern.get(i + 1).is_some_and(|&next| is_raw(next));
+                    let low = if before_raw { NO_OP } else { PATTERN };
+                    bytes.extend([PATTERN | byte >> 4, low | byte & 0x0F]);
+                }
+            }
+        }
+        bytes
+    }
+
+    /// encode the pattern as pattern bytes, one for each nibble. these
+    /// decode to the pattern both forward and backward, no matter what
+    /// bytes surround them.
+    pub fn to_pattern_bytes(self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            let nibble = (self.pattern >> (28 - i * 4)) as u8 & 0b0000_1111;
+            *byte = 0b1111_0000 | nibble;
+        }
+        bytes
+    }
+}
+
+// patterns are displayed as in the assembly language
+impl fmt::Display for BlockPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "@{}", Hex(self.pattern))?;
+        if self.wildcards != 0 {
+            write!(f, "~{}", Hex(self.wildcards))?;
+        }
+        Ok(())
     }
 }
 
-fn is_pattern_byte(byte: u8) -> bool {
+struct Hex(u32);
+
+impl fmt::Display for Hex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:04x}_{:04x}", self.0 >> 16, self.0 & 0xFFFF)
+    }
+}
+
+// whether a byte is a pattern byte, which holds a nibble of a pattern
+pub(crate) fn is_pattern_byte(byte: u8) -> bool {
     byte >> 4 == 0b1111
 }
 
+// the high nibble of pattern bytes, and of unknown0, a reserved no-op
+const PATTERN: u8 = 0xF0;
+const NO_OP: u8 = 0xD0;
+
+// whether a byte can encode itself in a pattern: it isn't part of a
+// pattern, and executing it doesn't split the block, jump, use the
+// pattern before it or halt
+fn is_raw(byte: u8) -> bool {
+    !matches!(
+        Op::decode(byte),
+        Op::Block
+            | Op::Call
+            | Op::Return
+            | Op::Value
+            | Op::If(_)
+            | Op::Repeat(_)
+            | Op::Push(_)
+            | Op::Pattern(_)
+    )
+}
+
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use super::*;
 
     #[test]
@@ -222,7 +456,7 @@ mod tests {
         let identifier = BlockPattern::decode_forward(&data, index);
         assert_eq!(
             identifier,
-            BlockPattern(0b0000_0001_0000_0010_0000_0100_0000_1000)
+            BlockPattern::new(0b0000_0001_0000_0010_0000_0100_0000_1000)
         );
     }
 
@@ -233,7 +467,272 @@ mod tests {
         let identifier = BlockPattern::decode_backward(&data, index);
         assert_eq!(
             identifier,
-            BlockPattern(0b0000_0001_0000_0010_0000_0100_0000_1000)
+            BlockPattern::new(0b0000_0001_0000_0010_0000_0100_0000_1000)
+        );
+    }
+
+    #[test]
+    fn test_to_pattern_bytes() {
+        let pattern = BlockPattern::new(0x1234_5678);
+        let bytes = pattern.to_pattern_bytes();
+        assert_eq!(bytes, [0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8]);
+        assert_eq!(BlockPattern::decode_forward(&bytes, 0), pattern);
+        assert_eq!(BlockPattern::decode_backward(&bytes, bytes.len()), pattern);
+    }
+
+    #[test]
+    fn test_to_pattern_bytes_surrounded() {
+        let pattern = BlockPattern::new(0x1234_5678);
+        let mut data = vec![0x18, 0x19];
+        data.extend(pattern.to_pattern_bytes());
+        data.extend([0x1A, 0x1B]);
+        assert_eq!(BlockPattern::decode_forward(&data, 2), pattern);
+        assert_eq!(BlockPattern::decode_backward(&data, 10), pattern);
+    }
+
+    #[test]
+    fn test_decode_block_identifier_backward_beyond_end() {
+        let data = [0b0000_0001, 0b0000_0010, 0b0000_0100, 0b0000_1000];
+        let identifier = BlockPattern::decode_backward(&data, data.len() + 10);
+        assert_eq!(
+            identifier,
+            BlockPattern::new(0b0000_0001_0000_0010_0000_0100_0000_1000)
+        );
+    }
+
+    #[test]
+    fn test_decode_block_identifier_forward_beyond_end() {
+        let data = [0b0000_0001];
+        let identifier = BlockPattern::decode_forward(&data, data.len() + 10);
+        assert_eq!(identifier, BlockPattern::new(0).with_present(0));
+        assert_eq!(identifier.length(), 0);
+    }
+
+    #[test]
+    fn test_decode_forward_length() {
+        // a lone pattern byte at the end still decodes to a byte
+        let pattern = BlockPattern::decode_forward(&[0xF1, 0xF2, 0xF3], 0);
+        assert_eq!(pattern.get(), 0x1230_0000);
+        assert_eq!(pattern.present(), 0xFFFF_0000);
+        assert_eq!(pattern.length(), 16);
+        let pattern = BlockPattern::decode_forward(&[0x12, 0x34, 0x56], 0);
+        assert_eq!(pattern.present(), 0xFFFF_FF00);
+        assert_eq!(pattern.length(), 24);
+    }
+
+    #[test]
+    fn test_decode_backward_length() {
+        let pattern = BlockPattern::decode_backward(&[0xF1, 0xF2, 0x18], 2);
+        assert_eq!(pattern.get(), 0x0000_0012);
+        assert_eq!(pattern.present(), 0x0000_00FF);
+        assert_eq!(pattern.length(), 8);
+        assert_eq!(BlockPattern::decode_backward(&[], 0).length(), 0);
+    }
+
+    #[test]
+    fn test_decode_backward_masked() {
+        let wildcards = BlockPattern::new(0x0000_FFFF).to_pattern_bytes();
+        let pattern = BlockPattern::new(0x1234_5678).to_pattern_bytes();
+        let data = [&wildcards[..], &pattern[..]].concat();
+        let decoded = BlockPattern::decode_backward_masked(&data, data.len());
+        assert_eq!(decoded.get(), 0x1234_5678);
+        assert_eq!(decoded.wildcards(), 0x0000_FFFF);
+        // without bytes before the pattern there are no wildcards
+        let decoded = BlockPattern::decode_backward_masked(&pattern, pattern.len());
+        assert_eq!(decoded, BlockPattern::new(0x1234_5678));
+        // and neither are there without pattern bytes before it
+        let data = [&[0x18, 0x19, 0x1A, 0x1B][..], &pattern[..]].concat();
+        let decoded = BlockPattern::decode_backward_masked(&data, data.len());
+        assert_eq!(decoded, BlockPattern::new(0x1234_5678));
+        // only the run of pattern bytes counts
+        let data = [&[0x18, 0xF1, 0xF2][..], &pattern[..]].concat();
+        let decoded = BlockPattern::decode_backward_masked(&data, data.len());
+        assert_eq!(decoded.wildcards(), 0x0000_0012);
+    }
+
+    #[test]
+    fn test_eq_ignores_present() {
+        let pattern = BlockPattern::decode_backward(&[0xF1, 0xF2], 2);
+        assert_eq!(pattern.length(), 8);
+        assert_eq!(pattern, BlockPattern::new(0x12));
+        assert_ne!(pattern, BlockPattern::new(0x12).with_wildcards(1));
+        let hash = |pattern: BlockPattern| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            pattern.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(pattern), hash(BlockPattern::new(0x12)));
+    }
+
+    #[test]
+    fn test_mask() {
+        let pattern = BlockPattern::new(0x1234_5678)
+            .with_present(0xFFFF_0000)
+            .with_wildcards(0x0F0F_0F0F);
+        assert_eq!(pattern.mask(Patterns::Fixed), u32::MAX);
+        assert_eq!(pattern.mask(Patterns::Variable), 0xFFFF_0000);
+        assert_eq!(pattern.mask(Patterns::Masked), 0xF0F0_0000);
+    }
+
+    #[test]
+    fn test_display() {
+        let pattern = BlockPattern::new(0x1234_5678);
+        assert_eq!(pattern.to_string(), "@0x1234_5678");
+        assert_eq!(
+            pattern.with_wildcards(0xFF).to_string(),
+            "@0x1234_5678~0x0000_00ff"
+        );
+    }
+
+    #[test]
+    fn test_encode_forward() {
+        let pattern = BlockPattern::new(0x1234_5678);
+        // the last byte needs a pair, whatever follows it
+        assert_eq!(
+            pattern.encode(Direction::Forward),
+            [0xF1, 0xF2, 0x34, 0x56, 0xD7, 0xF8]
         );
+        let pattern = BlockPattern::new(0x00F1_2300);
+        assert_eq!(
+            pattern.encode(Direction::Forward),
+            [0xF0, 0xF0, 0xFF, 0xF1, 0x23, 0xD0, 0xF0]
+        );
+    }
+
+    #[test]
+    fn test_encode_backward() {
+        let pattern = BlockPattern::new(0x1234_5678);
+        // the first byte needs a pair, whatever precedes it
+        assert_eq!(
+            pattern.encode(Direction::Backward),
+            [0xF1, 0xD2, 0x34, 0x56, 0x78]
+        );
+        let pattern = BlockPattern::new(0x00F1_2300);
+        assert_eq!(
+            pattern.encode(Direction::Backward),
+            [0xF0, 0xF0, 0xFF, 0xD1, 0x23, 0xF0, 0xF0]
+        );
+    }
+
+    #[test]
+    fn test_encode_backward_wildcards() {
+        let pattern = BlockPattern::new(0x1234_5678).with_wildcards(0xFF);
+        let bytes = pattern.encode(Direction::Backward);
+        assert_eq!(bytes.len(), 13);
+        assert_eq!(
+            BlockPattern::decode_backward_masked(&bytes, bytes.len()),
+            pattern
+        );
+    }
+
+    #[test]
+    fn test_no_op() {
+        // pairs are padded with a byte that does nothing
+        for nibble in 0..=0x0F {
+            assert!(matches!(Op::decode(NO_OP | nibble), Op::Unknown0(_, _)));
+            assert_eq!(Op::decode(PATTERN | nibble), Op::Pattern(nibble));
+        }
+    }
+
+    #[test]
+    fn test_encode_control_flow() {
+        // bytes that change the flow of execution are never raw
+        let pattern = BlockPattern::new(0x0000_0201);
+        let bytes = pattern.encode(Direction::Backward);
+        assert_eq!(bytes, [0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF2, 0xF0, 0xF1]);
+        let pattern = BlockPattern::new(0x1805_0918);
+        let bytes = pattern.encode(Direction::Forward);
+        assert_eq!(bytes, [0x18, 0xD0, 0xF5, 0xF0, 0xF9, 0xF1, 0xF8]);
+    }
+
+    // the bytes a pattern may be surrounded by: nothing, a block
+    // separator, instructions and each pattern byte
+    fn surroundings() -> Vec<Vec<u8>> {
+        let mut surroundings = vec![vec![], vec![0x00], vec![0x18], vec![0xD5], vec![0xF5, 0xF6]];
+        surroundings.extend((0..=0x0F).map(|nibble| vec![PATTERN | nibble]));
+        surroundings
+    }
+
+    // whether bytes decode to a pattern whatever surrounds them
+    fn decodes(bytes: &[u8], pattern: u32, direction: Direction) -> bool {
+        surroundings().iter().all(|around| {
+            let decoded = match direction {
+                Direction::Forward => BlockPattern::decode_forward(&[bytes, around].concat(), 0),
+                Direction::Backward => {
+                    // the pattern of a call
+                    let data = [around, bytes, &[0x01]].concat();
+                    BlockPattern::decode_backward(&data, data.len() - 1)
+                }
+            };
+            decoded == BlockPattern::new(pattern)
+        })
+    }
+
+    // the length of the shortest encoding a search finds, that encodes each
+    // byte of the pattern as itself or as any pair of pattern and no-op
+    // bytes, and decodes whatever surrounds it
+    fn search_shortest(pattern: u32, direction: Direction) -> usize {
+        let options = |byte: u8| {
+            let mut options = vec![vec![byte]];
+            for high in [PATTERN, NO_OP] {
+                for low in [PATTERN, NO_OP] {
+                    options.push(vec![high | byte >> 4, low | byte & 0x0F]);
+                }
+            }
+            options.retain(|option| {
+                option
+                    .iter()
+                    .all(|&byte| is_raw(byte) || is_pattern_byte(byte))
+            });
+            options
+        };
+        let mut encodings = vec![Vec::new()];
+        for byte in pattern.to_be_bytes() {
+            encodings = encodings
+                .iter()
+                .flat_map(|encoding| {
+                    options(byte)
+                        .into_iter()
+                        .map(move |option| [&encoding[..], &option[..]].concat())
+                })
+                .collect();
+        }
+        encodings
+            .iter()
+            .filter(|bytes| decodes(bytes, pattern, direction))
+            .map(|bytes| bytes.len())
+            .min()
+            .unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn test_encode_forward_decodes(
+            pattern in any::<u32>(),
+            after in prop::collection::vec(any::<u8>(), 0..4),
+        ) {
+            let bytes = BlockPattern::new(pattern).encode(Direction::Forward);
+            prop_assert!(!bytes.contains(&0));
+            prop_assert!(decodes(&bytes, pattern, Direction::Forward));
+            prop_assert_eq!(bytes.len(), search_shortest(pattern, Direction::Forward));
+            let data = [&bytes[..], &after[..]].concat();
+            prop_assert_eq!(BlockPattern::decode_forward(&data, 0), BlockPattern::new(pattern));
+        }
+
+        #[test]
+        fn test_encode_backward_decodes(
+            pattern in any::<u32>(),
+            before in prop::collection::vec(any::<u8>(), 0..4),
+        ) {
+            let bytes = BlockPattern::new(pattern).encode(Direction::Backward);
+            prop_assert!(!bytes.contains(&0));
+            prop_assert!(decodes(&bytes, pattern, Direction::Backward));
+            prop_assert_eq!(bytes.len(), search_shortest(pattern, Direction::Backward));
+            let data = [&before[..], &bytes[..], &[0x01]].concat();
+            prop_assert_eq!(
+                BlockPattern::decode_backward(&data, data.len() - 1),
+                BlockPattern::new(pattern)
+            );
+        }
     }
 }
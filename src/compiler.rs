@@ -0,0 +1,127 @@
+// the compiler pre-decodes instruction memory, so that execution doesn't
+// need to decode bytes or patterns. there is a code for every byte in
+// instruction memory, so the compiled program has the same pc and uses
+// the same fuel as the bytes. pattern bytes are no-ops, so a run of them
+// is executed at once.
+
+use crate::blockpattern::BlockPattern;
+use crate::core::instruction_end;
+use crate::instruction::{split_value_pattern, Op};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Code {
+    // the zero byte at the end of a block
+    End,
+    // a call, with the position of its call site
+    Call(u32),
+    // a value, with its register and value
+    Value(u8, u32),
+    // an if or repeat, with its register and the end of the instruction
+    // that follows it
+    If(u8, u32),
+    Repeat(u8, u32),
+    // a pattern byte, with the amount of pattern bytes from here on
+    Pattern(u32),
+    // any other operation
+    Op(Op),
+}
+
+// compile instruction memory. calls are numbered in order, like the
+// call sites of a program.
+pub(crate) fn compile(instruction_memory: &[u8]) -> Vec<Code> {
+    let mut code = Vec::with_capacity(instruction_memory.len());
+    let mut block_start = 0;
+    let mut call_site = 0;
+    for (index, &byte) in instruction_memory.iter().enumerate() {
+        code.push(match Op::decode(byte) {
+            Op::Block => {
+                block_start = index + 1;
+                Code::End
+            }
+            Op::Call => {
+                call_site += 1;
+                Code::Call(call_site - 1)
+            }
+            Op::Value => {
+                let pattern = BlockPattern::decode_backward(
+                    &instruction_memory[block_start..],
+                    index - block_start,
+                );
+                let (r0, value) = split_value_pattern(&pattern);
+                Code::Value(r0, value)
+            }
+            Op::If(r0) => Code::If(r0, instruction_end(instruction_memory, index + 1) as u32),
+            Op::Repeat(r0) => {
+                Code::Repeat(r0, instruction_end(instruction_memory, index + 1) as u32)
+            }
+            Op::Pattern(_) => Code::Pattern(1),
+            op => Code::Op(op),
+        });
+    }
+    // count the pattern bytes that follow each pattern byte
+    let mut following = 0;
+    for code in code.iter_mut().rev() {
+        if let Code::Pattern(count) = code {
+            following += 1;
+            *count = following;
+        } else {
+            following = 0;
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile() {
+        let code = compile(&[0x18, 0x01, 0x00, 0x04, 0xF1, 0x19, 0x01, 0x00]);
+        assert_eq!(
+            code,
+            vec![
+                Code::Op(Op::Inc(0)),
+                Code::Call(0),
+                Code::End,
+                Code::If(0, 6),
+                Code::Pattern(1),
+                Code::Op(Op::Inc(1)),
+                Code::Call(1),
+                Code::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_value() {
+        // the pattern of a value doesn't extend into the previous block
+        let code = compile(&[0xF1, 0x00, 0xFA, 0xFA, 0x03]);
+        let (r0, value) = split_value_pattern(&BlockPattern::decode_backward(&[0xFA, 0xFA], 2));
+        assert_eq!(code[4], Code::Value(r0, value));
+    }
+
+    #[test]
+    fn test_compile_pattern_runs() {
+        let code = compile(&[0xF1, 0xF2, 0xF3, 0x18, 0xF4, 0x00, 0xF5]);
+        assert_eq!(
+            code,
+            vec![
+                Code::Pattern(3),
+                Code::Pattern(2),
+                Code::Pattern(1),
+                Code::Op(Op::Inc(0)),
+                Code::Pattern(1),
+                Code::End,
+                Code::Pattern(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_nested_repeat() {
+        let code = compile(&[0x08, 0x05, 0x1A, 0x1B]);
+        assert_eq!(code[0], Code::Repeat(0, 3));
+        assert_eq!(code[1], Code::If(1, 3));
+    }
+}
@@ -1,8 +1,18 @@
+use rand::rngs::SmallRng;
+
+use crate::blockid::BlockId;
+use crate::blockpattern::BlockPattern;
+use crate::compiler::Code;
+use crate::instruction::{split_value_pattern, Op};
+use crate::linker::{Binding, Linking, Program};
+use crate::structure::LookupStats;
+
 // the executor trait can execute actual instructions
 trait Executor {
     fn call(&mut self);
     fn return_(&mut self);
-    fn value(&mut self); // this instruction actually would need to take a register too
+    // the register and the value are taken from the pattern before the instruction
+    fn value(&mut self);
     fn if_(&mut self, r0: u8);
     fn repeat(&mut self, r0: u8);
     fn not(&mut self, r0: u8);
@@ -25,163 +35,537 @@ trait Executor {
     fn unknown1(&mut self, r0: u8, r1: u8);
 }
 
-// decode and execute a byte instruction
-// we do this with simple direct decoding
+// decode and execute a byte instruction, using the decode table
 fn execute<E: Executor>(executor: &mut E, instruction: u8) {
-    // first decode the opcode
-    let opcode = instruction >> 4;
-    match opcode {
-        0 => execute_special0(executor, instruction),
-        1 => execute_special1(executor, instruction & 0b0000_1111),
-        opcode @ 2..=14 => {
-            // the operand is the low nibble, consisting of two two-bit registers
-            let r0 = instruction & 0b0000_1100 >> 2;
-            let r1 = instruction & 0b0000_0011;
-            match opcode {
-                2 => executor.store(r0, r1),
-                3 => executor.load(r0, r1),
-                4 => executor.add(r0, r1),
-                5 => executor.sub(r0, r1),
-                6 => executor.mul(r0, r1),
-                7 => executor.div(r0, r1),
-                8 => executor.eq(r0, r1),
-                9 => executor.gt(r0, r1),
-                10 => executor.and(r0, r1),
-                11 => executor.or(r0, r1),
-                12 => executor.xor(r0, r1),
-                13 => executor.unknown0(r0, r1),
-                14 => executor.unknown1(r0, r1),
-                _ => unreachable!(),
-            }
-        }
-        15 => {
-            // pattern is a no-op
-        }
-        _ => unreachable!(),
-    }
+    execute_op(executor, Op::decode(instruction))
 }
 
-fn execute_special0<E: Executor>(executor: &mut E, instruction: u8) {
-    match instruction {
-        0 => {
+fn execute_op<E: Executor>(executor: &mut E, op: Op) {
+    match op {
+        Op::Block => {
             // block is a no-op
         }
-        1 => {
-            executor.call();
-        }
-        2 => {
-            executor.return_();
-        }
-        3 => {
-            executor.value();
-        }
-        4..=7 => {
-            executor.if_(register_operand(instruction));
-        }
-        8..=11 => {
-            executor.repeat(register_operand(instruction));
-        }
-        12..=15 => {
-            executor.not(register_operand(instruction));
+        Op::Call => executor.call(),
+        Op::Return => executor.return_(),
+        Op::Value => executor.value(),
+        Op::If(r0) => executor.if_(r0),
+        Op::Repeat(r0) => executor.repeat(r0),
+        Op::Not(r0) => executor.not(r0),
+        Op::Push(r0) => executor.push(r0),
+        Op::Pop(r0) => executor.pop(r0),
+        Op::Inc(r0) => executor.inc(r0),
+        Op::Dec(r0) => executor.dec(r0),
+        Op::Store(r0, r1) => executor.store(r0, r1),
+        Op::Load(r0, r1) => executor.load(r0, r1),
+        Op::Add(r0, r1) => executor.add(r0, r1),
+        Op::Sub(r0, r1) => executor.sub(r0, r1),
+        Op::Mul(r0, r1) => executor.mul(r0, r1),
+        Op::Div(r0, r1) => executor.div(r0, r1),
+        Op::Eq(r0, r1) => executor.eq(r0, r1),
+        Op::Gt(r0, r1) => executor.gt(r0, r1),
+        Op::And(r0, r1) => executor.and(r0, r1),
+        Op::Or(r0, r1) => executor.or(r0, r1),
+        Op::Xor(r0, r1) => executor.xor(r0, r1),
+        Op::Unknown0(r0, r1) => executor.unknown0(r0, r1),
+        Op::Unknown1(r0, r1) => executor.unknown1(r0, r1),
+        Op::Pattern(_) => {
+            // pattern is a no-op
         }
-        _ => unreachable!(),
     }
 }
 
-fn execute_special1<E: Executor>(executor: &mut E, instruction: u8) {
-    match instruction {
-        0..=3 => {
-            executor.push(register_operand(instruction));
-        }
-        4..=7 => {
-            executor.pop(register_operand(instruction));
+// the end of the instruction starting at index. pattern bytes are
+// no-ops that belong to the instruction that follows them. if and repeat
+// include the instruction that follows them. an instruction never
+// extends beyond the end of the block.
+pub(crate) fn instruction_end(instruction_memory: &[u8], mut index: usize) -> usize {
+    loop {
+        let op = match instruction_memory.get(index) {
+            Some(&instruction) => Op::decode(instruction),
+            None => return index,
+        };
+        match op {
+            Op::Block => return index,
+            Op::Pattern(_) | Op::If(_) | Op::Repeat(_) => index += 1,
+            _ => return index + 1,
         }
-        8..=11 => {
-            executor.inc(register_operand(instruction));
-        }
-        12..=15 => {
-            executor.dec(register_operand(instruction));
-        }
-        _ => unreachable!(),
     }
 }
 
-fn register_operand(instruction: u8) -> u8 {
-    instruction & 0b0000_0011
+// the maximum amount of values on the stack
+const MAX_STACK_SIZE: usize = 256;
+// the maximum amount of nested calls
+const MAX_CALL_DEPTH: usize = 64;
+// the maximum amount of times a repeat executes its instruction
+const MAX_REPEAT_COUNT: u32 = 256;
+
+/// why the processor stopped running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionOutcome {
+    /// we reached the end of the entry block
+    Finished,
+    /// we ran out of fuel before the program finished
+    OutOfFuel,
+    /// a push was executed on a full stack
+    StackOverflow,
+    /// a call was executed at the maximum call depth
+    CallDepthExceeded,
+    /// a return was executed in the entry block
+    ReturnedFromEntryBlock,
+}
+
+/// how the processor executes a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpreter {
+    /// decode every byte when it is executed
+    Bytes,
+    /// execute the pre-decoded program, which is faster
+    #[default]
+    Compiled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Execution {
+    pub outcome: ExecutionOutcome,
+    /// the amount of instructions executed
+    pub steps: u64,
+}
+
+// where to continue after a called block returns
+#[derive(Debug)]
+struct Frame {
+    block: BlockId,
+    return_pc: usize,
 }
 
-struct Processor<'a> {
-    instruction_memory: Vec<u8>,
+// a repeat that is in progress
+#[derive(Debug)]
+struct Loop {
+    // where the repeated instruction starts and ends
+    start: usize,
+    end: usize,
+    // how many more times we need to execute it
+    remaining: u32,
+    // the call depth at which the repeat was executed
+    call_depth: usize,
+}
+
+pub(crate) struct Processor<'a> {
+    program: &'a Program,
+    // with stochastic linking, the binding of each call site for this execution
+    bindings: Vec<Binding>,
+    rng: SmallRng,
     main_memory: &'a mut [u8],
     main_memory_mask: usize,
+    // the block we are currently executing
+    block: BlockId,
     pc: usize,
+    call_stack: Vec<Frame>,
+    loops: Vec<Loop>,
     stack: Vec<u32>,
     registers: [u32; 4],
+    // set by an instruction that stops execution
+    halted: Option<ExecutionOutcome>,
+    // the lookups of blocks by this execution. the program counts the
+    // lookups of all executions that share it.
+    lookups: LookupStats,
 }
 
 impl<'a> Processor<'a> {
-    fn new(main_memory: &'a mut [u8]) -> Processor {
+    // execution starts at the first block of the first strand
+    pub(crate) fn new(
+        program: &'a Program,
+        main_memory: &'a mut [u8],
+        mut rng: SmallRng,
+    ) -> Processor<'a> {
         assert!(main_memory.len().is_power_of_two());
         // check how many bits are needed to address the main memory
         let main_memory_bits = main_memory.len().trailing_zeros();
         // construct a mask for this number of bits
         let main_memory_mask = (1 << main_memory_bits) - 1;
 
+        let mut lookups = LookupStats::default();
+        let bindings = match program.linking {
+            Linking::Stochastic => program.bind_stochastic(&mut rng, &mut lookups),
+            _ => Vec::new(),
+        };
+
         Processor {
-            instruction_memory: Vec::new(),
+            program,
+            bindings,
+            rng,
             main_memory,
             main_memory_mask,
+            block: BlockId::new(0, 0),
             pc: 0,
+            call_stack: Vec::new(),
+            loops: Vec::new(),
             stack: Vec::new(),
             registers: [0; 4],
+            halted: None,
+            lookups,
         }
     }
+
+    // run the program from the current pc until it halts. Every executed
+    // instruction costs one unit of fuel; if we run out the program is halted,
+    // and it can be resumed by calling run again.
+    pub(crate) fn run(&mut self, fuel: u64) -> Execution {
+        self.run_with(fuel, |processor, _| {
+            let instruction = processor.program.instruction_memory[processor.pc];
+            processor.pc += 1;
+            execute(processor, instruction);
+            1
+        })
+    }
+
+    // run the compiled program. this behaves exactly like run, but doesn't
+    // need to decode anything.
+    pub(crate) fn run_compiled(&mut self, fuel: u64) -> Execution {
+        self.run_with(fuel, |processor, fuel| {
+            let code = processor.program.code[processor.pc];
+            processor.pc += 1;
+            match code {
+                Code::Pattern(count) => {
+                    // skip over all pattern bytes, as far as the fuel lasts.
+                    // no loop can end in between pattern bytes.
+                    let count = (count as u64).min(fuel);
+                    processor.pc += count as usize - 1;
+                    return count;
+                }
+                Code::End => {
+                    // we never execute the end of a block
+                }
+                Code::Call(position) => {
+                    let position = position as usize;
+                    let target = match processor.program.linking {
+                        Linking::Dynamic => {
+                            processor.lookup(processor.program.call_sites()[position].pattern)
+                        }
+                        _ => processor.bound_target(position),
+                    };
+                    processor.call_block(target);
+                }
+                Code::Value(r0, value) => processor.set_register(r0, value),
+                Code::If(r0, end) => processor.if_to(r0, end as usize),
+                Code::Repeat(r0, end) => processor.repeat_to(r0, end as usize),
+                Code::Op(op) => execute_op(processor, op),
+            }
+            1
+        })
+    }
+
+    // run until halted, executing instructions with step. step gets the
+    // remaining fuel, which is never 0, and returns the fuel it used.
+    #[inline(always)]
+    fn run_with(&mut self, fuel: u64, step: impl Fn(&mut Self, u64) -> u64) -> Execution {
+        let mut steps = 0;
+        let outcome = loop {
+            self.continue_loops();
+            if self.at_block_end() {
+                // reaching the end of a called block returns from it
+                if !self.return_from_block() {
+                    break ExecutionOutcome::Finished;
+                }
+                continue;
+            }
+            if steps >= fuel {
+                break ExecutionOutcome::OutOfFuel;
+            }
+            steps += step(self, fuel - steps);
+            if let Some(outcome) = self.halted.take() {
+                break outcome;
+            }
+        };
+        Execution { outcome, steps }
+    }
+
+    // a block ends with a zero byte, or at the end of instruction memory
+    fn at_block_end(&self) -> bool {
+        self.pc >= self.program.instruction_memory.len()
+            || self.program.instruction_memory[self.pc] == 0
+    }
+
+    // return to the caller of the current block. returns false if we
+    // are in the entry block, which has no caller.
+    fn return_from_block(&mut self) -> bool {
+        match self.call_stack.pop() {
+            Some(frame) => {
+                self.block = frame.block;
+                self.pc = frame.return_pc;
+                // any repeats in the called block are abandoned
+                let call_depth = self.call_stack.len();
+                while matches!(self.loops.last(), Some(l) if l.call_depth > call_depth) {
+                    self.loops.pop();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    // if we reached the end of a repeated instruction, go back to its start
+    // when it needs to be executed again. nested repeats end at the same
+    // place, so we may finish multiple loops at once.
+    fn continue_loops(&mut self) {
+        let call_depth = self.call_stack.len();
+        while let Some(l) = self.loops.last_mut() {
+            if l.call_depth != call_depth || l.end != self.pc {
+                return;
+            }
+            if l.remaining > 0 {
+                l.remaining -= 1;
+                self.pc = l.start;
+                return;
+            }
+            self.loops.pop();
+        }
+    }
+
+    fn instruction_end(&self, index: usize) -> usize {
+        instruction_end(&self.program.instruction_memory, index)
+    }
+
+    // the pattern in the bytes before the instruction we are executing,
+    // within the current block
+    fn pattern_before_instruction(&self) -> BlockPattern {
+        let block_start = self.block_start(&self.block);
+        let instruction_index = self.pc - 1;
+        self.program.blocks.call_pattern(
+            &self.program.instruction_memory[block_start..],
+            instruction_index - block_start,
+        )
+    }
+
+    // look up the block to call from the current block during execution
+    fn lookup(&mut self, pattern: BlockPattern) -> Option<BlockId> {
+        let lookup =
+            self.program
+                .blocks
+                .lookup_match(pattern, self.block.block_index(), &mut self.rng);
+        self.lookups.record(&lookup);
+        lookup.found.map(|found| found.value.clone())
+    }
+
+    // the block a call site is bound to, for a program that isn't linked
+    // dynamically
+    fn bound_target(&self, position: usize) -> Option<BlockId> {
+        let binding = match self.program.linking {
+            Linking::Stochastic => &self.bindings[position],
+            _ => &self.program.call_sites()[position].binding,
+        };
+        match binding {
+            Binding::Block(target) => Some(target.clone()),
+            _ => None,
+        }
+    }
+
+    // call the target block, if there is one
+    fn call_block(&mut self, target: Option<BlockId>) {
+        let target = match target {
+            Some(target) => target,
+            None => return,
+        };
+        if self.call_stack.len() >= MAX_CALL_DEPTH {
+            self.halted = Some(ExecutionOutcome::CallDepthExceeded);
+            return;
+        }
+        let target_start = self.block_start(&target);
+        let return_pc = std::mem::replace(&mut self.pc, target_start);
+        let caller = std::mem::replace(&mut self.block, target);
+        self.call_stack.push(Frame {
+            block: caller,
+            return_pc,
+        });
+    }
+
+    // skip to the end of the instruction after an if if its register is 0
+    fn if_to(&mut self, r0: u8, end: usize) {
+        if self.register(r0) == 0 {
+            self.pc = end;
+        }
+    }
+
+    // repeat the instruction after a repeat, which ends at end
+    fn repeat_to(&mut self, r0: u8, end: usize) {
+        let count = self.register(r0).min(MAX_REPEAT_COUNT);
+        let start = self.pc;
+        if count == 0 {
+            self.pc = end;
+            return;
+        }
+        // an empty instruction at the end of the block isn't worth repeating
+        if count > 1 && end > start {
+            self.loops.push(Loop {
+                start,
+                end,
+                remaining: count - 1,
+                call_depth: self.call_stack.len(),
+            });
+        }
+    }
+
+    fn block_start(&self, block_id: &BlockId) -> usize {
+        self.program.block_starts[block_id.strand_id()][block_id.block_index()]
+    }
+
+    pub(crate) fn lookup_stats(&self) -> LookupStats {
+        self.lookups
+    }
+
+    pub(crate) fn registers(&self) -> [u32; 4] {
+        self.registers
+    }
+
+    pub(crate) fn set_registers(&mut self, registers: [u32; 4]) {
+        self.registers = registers;
+    }
+
+    // addresses wrap around the end of main memory, so a 32 bit value
+    // can be read or written at any address without overflowing
+    fn memory_index(&self, address: u32, offset: usize) -> usize {
+        (address as usize).wrapping_add(offset) & self.main_memory_mask
+    }
+
+    fn register(&self, r: u8) -> u32 {
+        self.registers[r as usize]
+    }
+
+    fn set_register(&mut self, r: u8, value: u32) {
+        self.registers[r as usize] = value;
+    }
+
+    // apply a binary operation to r0 and r1, storing the result in r0
+    fn binary(&mut self, r0: u8, r1: u8, f: impl Fn(u32, u32) -> u32) {
+        let value = f(self.register(r0), self.register(r1));
+        self.set_register(r0, value);
+    }
 }
 
+// The semantics of the processor are total: every instruction is defined for
+// every possible register value, so no byte string can cause a panic.
+//
+// - arithmetic wraps around on overflow and underflow
+// - division by zero results in 0
+// - eq and gt result in 1 if true and 0 if false
+// - not is a logical not: 0 becomes 1, anything else becomes 0
+// - popping an empty stack results in 0
+// - pushing onto a full stack halts the processor with a stack overflow
+// - memory addresses wrap around the end of main memory
+// - value loads the value in the pattern before it into a register
+// - a call that doesn't match any block is a no-op
+// - a call at the maximum call depth halts the processor
+// - a return in the entry block halts the processor
+// - if skips the instruction that follows it if its register is 0
+// - repeat executes the instruction that follows it as many times as the
+//   value its register has when the repeat is executed, up to a maximum.
+//   if the register is 0 the instruction is skipped.
 impl<'a> Executor for Processor<'a> {
-    fn call(&mut self) {}
-    fn return_(&mut self) {}
-    fn value(&mut self) {}
-    fn if_(&mut self, r0: u8) {}
-    fn repeat(&mut self, r0: u8) {}
-    fn not(&mut self, r0: u8) {}
-    fn push(&mut self, r0: u8) {}
-    fn pop(&mut self, r0: u8) {}
-    fn inc(&mut self, r0: u8) {}
-    fn dec(&mut self, r0: u8) {}
+    fn call(&mut self) {
+        let target = match self.program.linking {
+            Linking::Dynamic => self.lookup(self.pattern_before_instruction()),
+            _ => self.bound_target(self.program.call_site_position(self.pc - 1)),
+        };
+        self.call_block(target);
+    }
+    fn return_(&mut self) {
+        if !self.return_from_block() {
+            self.halted = Some(ExecutionOutcome::ReturnedFromEntryBlock);
+        }
+    }
+    fn value(&mut self) {
+        let pattern = self.pattern_before_instruction();
+        let (r0, value) = split_value_pattern(&pattern);
+        self.set_register(r0, value);
+    }
+    fn if_(&mut self, r0: u8) {
+        let end = self.instruction_end(self.pc);
+        self.if_to(r0, end);
+    }
+    fn repeat(&mut self, r0: u8) {
+        let end = self.instruction_end(self.pc);
+        self.repeat_to(r0, end);
+    }
+    fn not(&mut self, r0: u8) {
+        let value = (self.register(r0) == 0) as u32;
+        self.set_register(r0, value);
+    }
+    fn push(&mut self, r0: u8) {
+        if self.stack.len() >= MAX_STACK_SIZE {
+            self.halted = Some(ExecutionOutcome::StackOverflow);
+            return;
+        }
+        self.stack.push(self.register(r0));
+    }
+    fn pop(&mut self, r0: u8) {
+        let value = self.stack.pop().unwrap_or(0);
+        self.set_register(r0, value);
+    }
+    fn inc(&mut self, r0: u8) {
+        self.set_register(r0, self.register(r0).wrapping_add(1));
+    }
+    fn dec(&mut self, r0: u8) {
+        self.set_register(r0, self.register(r0).wrapping_sub(1));
+    }
     fn store(&mut self, r0: u8, r1: u8) {
-        let address = self.registers[r0 as usize] as usize & self.main_memory_mask;
-        let value = self.registers[r1 as usize];
-        // we should check for overflow first
+        let address = self.register(r0);
+        let value = self.register(r1);
         // write the value to the memory, little-endian
-        self.main_memory[address] = value as u8;
-
-        self.main_memory[address + 1] = (value >> 8) as u8;
-        self.main_memory[address + 2] = (value >> 16) as u8;
-        self.main_memory[address + 3] = (value >> 24) as u8;
+        for (offset, byte) in value.to_le_bytes().into_iter().enumerate() {
+            let index = self.memory_index(address, offset);
+            self.main_memory[index] = byte;
+        }
     }
     fn load(&mut self, r0: u8, r1: u8) {
-        let address = self.registers[r0 as usize] as usize & self.main_memory_mask;
-
+        let address = self.register(r0);
         // load the value from the memory, little-endian
-        // we should check for overflow first
-        // u32::from_le_bytes(&self.main_memory[address..address + 4])
-    }
-    fn add(&mut self, r0: u8, r1: u8) {}
-    fn sub(&mut self, r0: u8, r1: u8) {}
-    fn mul(&mut self, r0: u8, r1: u8) {}
-    fn div(&mut self, r0: u8, r1: u8) {}
-    fn eq(&mut self, r0: u8, r1: u8) {}
-    fn gt(&mut self, r0: u8, r1: u8) {}
-    fn and(&mut self, r0: u8, r1: u8) {}
-    fn or(&mut self, r0: u8, r1: u8) {}
-    fn xor(&mut self, r0: u8, r1: u8) {}
-    fn unknown0(&mut self, r0: u8, r1: u8) {}
-    fn unknown1(&mut self, r0: u8, r1: u8) {}
+        let mut bytes = [0; 4];
+        for (offset, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.main_memory[self.memory_index(address, offset)];
+        }
+        self.set_register(r1, u32::from_le_bytes(bytes));
+    }
+    fn add(&mut self, r0: u8, r1: u8) {
+        self.binary(r0, r1, u32::wrapping_add);
+    }
+    fn sub(&mut self, r0: u8, r1: u8) {
+        self.binary(r0, r1, u32::wrapping_sub);
+    }
+    fn mul(&mut self, r0: u8, r1: u8) {
+        self.binary(r0, r1, u32::wrapping_mul);
+    }
+    fn div(&mut self, r0: u8, r1: u8) {
+        self.binary(r0, r1, |a, b| a.checked_div(b).unwrap_or(0));
+    }
+    fn eq(&mut self, r0: u8, r1: u8) {
+        self.binary(r0, r1, |a, b| (a == b) as u32);
+    }
+    fn gt(&mut self, r0: u8, r1: u8) {
+        self.binary(r0, r1, |a, b| (a > b) as u32);
+    }
+    fn and(&mut self, r0: u8, r1: u8) {
+        self.binary(r0, r1, |a, b| a & b);
+    }
+    fn or(&mut self, r0: u8, r1: u8) {
+        self.binary(r0, r1, |a, b| a | b);
+    }
+    fn xor(&mut self, r0: u8, r1: u8) {
+        self.binary(r0, r1, |a, b| a ^ b);
+    }
+    // the unknown instructions are reserved, and are no-ops for now
+    fn unknown0(&mut self, _r0: u8, _r1: u8) {}
+    fn unknown1(&mut self, _r0: u8, _r1: u8) {}
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+    use rand::SeedableRng;
+
+    use crate::instruction::{BlockRef, Instruction, RegisterId};
+    use crate::linker::link;
+    use crate::structure::{Blocks, Strand};
+
     use super::*;
 
     struct TestExecutor {
@@ -314,4 +698,723 @@ mod tests {
         execute(&mut executor, 0b0001_1111);
         assert_eq!(executor.trace, vec!["dec 3".to_string()]);
     }
+
+    #[test]
+    fn test_add_r2_r1() {
+        let mut executor = TestExecutor::new();
+        execute(&mut executor, 0b0100_1001);
+        assert_eq!(executor.trace, vec!["add 2 1".to_string()]);
+    }
+
+    // an executor that records the instruction it executed
+    struct InstructionExecutor {
+        instructions: Vec<Instruction>,
+    }
+
+    impl InstructionExecutor {
+        fn record(&mut self, instruction: Instruction) {
+            self.instructions.push(instruction);
+        }
+    }
+
+    impl Executor for InstructionExecutor {
+        fn call(&mut self) {
+            // a lone call has an empty pattern
+            self.record(Instruction::Call(BlockRef::Pattern(
+                BlockPattern::new(0).with_present(0),
+            )));
+        }
+        fn return_(&mut self) {
+            self.record(Instruction::Return);
+        }
+        fn value(&mut self) {
+            self.record(Instruction::Value(RegisterId(0), 0));
+        }
+        fn if_(&mut self, r0: u8) {
+            self.record(Instruction::If(RegisterId(r0)));
+        }
+        fn repeat(&mut self, r0: u8) {
+            self.record(Instruction::Repeat(RegisterId(r0)));
+        }
+        fn not(&mut self, r0: u8) {
+            self.record(Instruction::Not(RegisterId(r0)));
+        }
+        fn push(&mut self, r0: u8) {
+            self.record(Instruction::Push(RegisterId(r0)));
+        }
+        fn pop(&mut self, r0: u8) {
+            self.record(Instruction::Pop(RegisterId(r0)));
+        }
+        fn inc(&mut self, r0: u8) {
+            self.record(Instruction::Inc(RegisterId(r0)));
+        }
+        fn dec(&mut self, r0: u8) {
+            self.record(Instruction::Dec(RegisterId(r0)));
+        }
+        fn store(&mut self, r0: u8, r1: u8) {
+            self.record(Instruction::Store(RegisterId(r0), RegisterId(r1)));
+        }
+        fn load(&mut self, r0: u8, r1: u8) {
+            self.record(Instruction::Load(RegisterId(r0), RegisterId(r1)));
+        }
+        fn add(&mut self, r0: u8, r1: u8) {
+            self.record(Instruction::Add(RegisterId(r0), RegisterId(r1)));
+        }
+        fn sub(&mut self, r0: u8, r1: u8) {
+            self.record(Instruction::Sub(RegisterId(r0), RegisterId(r1)));
+        }
+        fn mul(&mut self, r0: u8, r1: u8) {
+            self.record(Instruction::Mul(RegisterId(r0), RegisterId(r1)));
+        }
+        fn div(&mut self, r0: u8, r1: u8) {
+            self.record(Instruction::Div(RegisterId(r0), RegisterId(r1)));
+        }
+        fn eq(&mut self, r0: u8, r1: u8) {
+            self.record(Instruction::Eq(RegisterId(r0), RegisterId(r1)));
+        }
+        fn gt(&mut self, r0: u8, r1: u8) {
+            self.record(Instruction::Gt(RegisterId(r0), RegisterId(r1)));
+        }
+        fn and(&mut self, r0: u8, r1: u8) {
+            self.record(Instruction::And(RegisterId(r0), RegisterId(r1)));
+        }
+        fn or(&mut self, r0: u8, r1: u8) {
+            self.record(Instruction::Or(RegisterId(r0), RegisterId(r1)));
+        }
+        fn xor(&mut self, r0: u8, r1: u8) {
+            self.record(Instruction::Xor(RegisterId(r0), RegisterId(r1)));
+        }
+        fn unknown0(&mut self, r0: u8, r1: u8) {
+            self.record(Instruction::Unknown0(RegisterId(r0), RegisterId(r1)));
+        }
+        fn unknown1(&mut self, r0: u8, r1: u8) {
+            self.record(Instruction::Unknown1(RegisterId(r0), RegisterId(r1)));
+        }
+    }
+
+    #[test]
+    fn test_execute_agrees_with_decode_for_all_bytes() {
+        for byte in 0..=255 {
+            let mut executor = InstructionExecutor {
+                instructions: Vec::new(),
+            };
+            execute(&mut executor, byte);
+            let expected = match Instruction::decode(&[byte], 0) {
+                // these are no-ops for the executor
+                Instruction::Block | Instruction::Pattern(_) => vec![],
+                instruction => vec![instruction],
+            };
+            assert_eq!(executor.instructions, expected, "byte {:#04x}", byte);
+        }
+    }
+
+    struct Conformance {
+        name: &'static str,
+        registers: [u32; 4],
+        operation: fn(&mut Processor),
+        expected: [u32; 4],
+    }
+
+    const MAX: u32 = u32::MAX;
+
+    #[rustfmt::skip]
+    const CONFORMANCE: &[Conformance] = &[
+        Conformance { name: "add", registers: [1, 2, 0, 0], operation: |p| p.add(0, 1), expected: [3, 2, 0, 0] },
+        Conformance { name: "add wraps", registers: [MAX, 2, 0, 0], operation: |p| p.add(0, 1), expected: [1, 2, 0, 0] },
+        Conformance { name: "add to self", registers: [0, 0, 0, 21], operation: |p| p.add(3, 3), expected: [0, 0, 0, 42] },
+        Conformance { name: "sub", registers: [5, 3, 0, 0], operation: |p| p.sub(0, 1), expected: [2, 3, 0, 0] },
+        Conformance { name: "sub wraps", registers: [0, 1, 0, 0], operation: |p| p.sub(0, 1), expected: [MAX, 1, 0, 0] },
+        Conformance { name: "mul", registers: [6, 7, 0, 0], operation: |p| p.mul(0, 1), expected: [42, 7, 0, 0] },
+        Conformance { name: "mul wraps", registers: [1 << 31, 2, 0, 0], operation: |p| p.mul(0, 1), expected: [0, 2, 0, 0] },
+        Conformance { name: "div", registers: [7, 2, 0, 0], operation: |p| p.div(0, 1), expected: [3, 2, 0, 0] },
+        Conformance { name: "div by zero", registers: [7, 0, 0, 0], operation: |p| p.div(0, 1), expected: [0, 0, 0, 0] },
+        Conformance { name: "div max", registers: [MAX, MAX, 0, 0], operation: |p| p.div(0, 1), expected: [1, MAX, 0, 0] },
+        Conformance { name: "eq true", registers: [4, 4, 0, 0], operation: |p| p.eq(0, 1), expected: [1, 4, 0, 0] },
+        Conformance { name: "eq false", registers: [4, 5, 0, 0], operation: |p| p.eq(0, 1), expected: [0, 5, 0, 0] },
+        Conformance { name: "gt true", registers: [MAX, 0, 0, 0], operation: |p| p.gt(0, 1), expected: [1, 0, 0, 0] },
+        Conformance { name: "gt false", registers: [4, 4, 0, 0], operation: |p| p.gt(0, 1), expected: [0, 4, 0, 0] },
+        Conformance { name: "and", registers: [0b1100, 0b1010, 0, 0], operation: |p| p.and(0, 1), expected: [0b1000, 0b1010, 0, 0] },
+        Conformance { name: "or", registers: [0b1100, 0b1010, 0, 0], operation: |p| p.or(0, 1), expected: [0b1110, 0b1010, 0, 0] },
+        Conformance { name: "xor", registers: [0b1100, 0b1010, 0, 0], operation: |p| p.xor(0, 1), expected: [0b0110, 0b1010, 0, 0] },
+        Conformance { name: "xor self", registers: [0, 0, MAX, 0], operation: |p| p.xor(2, 2), expected: [0, 0, 0, 0] },
+        Conformance { name: "not zero", registers: [0, 0, 0, 0], operation: |p| p.not(2), expected: [0, 0, 1, 0] },
+        Conformance { name: "not non-zero", registers: [0, 0, MAX, 0], operation: |p| p.not(2), expected: [0, 0, 0, 0] },
+        Conformance { name: "inc", registers: [0, 41, 0, 0], operation: |p| p.inc(1), expected: [0, 42, 0, 0] },
+        Conformance { name: "inc wraps", registers: [0, MAX, 0, 0], operation: |p| p.inc(1), expected: [0, 0, 0, 0] },
+        Conformance { name: "dec", registers: [0, 0, 0, 43], operation: |p| p.dec(3), expected: [0, 0, 0, 42] },
+        Conformance { name: "dec wraps", registers: [0, 0, 0, 0], operation: |p| p.dec(3), expected: [0, 0, 0, MAX] },
+        Conformance { name: "push pop", registers: [42, 0, 0, 0], operation: |p| { p.push(0); p.pop(1) }, expected: [42, 42, 0, 0] },
+        Conformance { name: "pop empty", registers: [0, 42, 0, 0], operation: |p| p.pop(1), expected: [0, 0, 0, 0] },
+        Conformance { name: "store load", registers: [8, 0x1234_5678, 0, 0], operation: |p| { p.store(0, 1); p.load(0, 2) }, expected: [8, 0x1234_5678, 0x1234_5678, 0] },
+        Conformance { name: "load empty", registers: [8, 0, 0, 42], operation: |p| p.load(0, 3), expected: [8, 0, 0, 0] },
+        Conformance { name: "store load wraps", registers: [14, MAX - 1, 0, 0], operation: |p| { p.store(0, 1); p.load(0, 2) }, expected: [14, MAX - 1, MAX - 1, 0] },
+        Conformance { name: "store load address masked", registers: [MAX, 42, 0, 0], operation: |p| { p.store(0, 1); p.load(0, 2) }, expected: [MAX, 42, 42, 0] },
+        Conformance { name: "unknown0", registers: [1, 2, 3, 4], operation: |p| p.unknown0(0, 1), expected: [1, 2, 3, 4] },
+        Conformance { name: "unknown1", registers: [1, 2, 3, 4], operation: |p| p.unknown1(0, 1), expected: [1, 2, 3, 4] },
+    ];
+
+    fn link_bytes(
+        strands: &[&[u8]],
+        max_distance: u32,
+        match_chance: f64,
+        linking: Linking,
+    ) -> Program {
+        let strands = strands
+            .iter()
+            .map(|bytes| Strand::from_bytes(bytes))
+            .collect::<Vec<_>>();
+        let blocks = Blocks::new(max_distance, match_chance, &strands);
+        link(&strands, blocks, linking)
+    }
+
+    // run f with a processor for the given strands. calls only match
+    // exactly, and always do so.
+    fn with_processor<T>(strands: &[&[u8]], f: impl FnOnce(&mut Processor) -> T) -> T {
+        let program = link_bytes(strands, 0, 1.0, Linking::Dynamic);
+        let mut main_memory = [0; 16];
+        let mut processor =
+            Processor::new(&program, &mut main_memory, SmallRng::from_seed([0; 32]));
+        f(&mut processor)
+    }
+
+    #[test]
+    fn test_conformance() {
+        for conformance in CONFORMANCE {
+            with_processor(&[], |processor| {
+                processor.registers = conformance.registers;
+                (conformance.operation)(processor);
+                assert_eq!(
+                    processor.registers, conformance.expected,
+                    "{}",
+                    conformance.name
+                );
+            });
+        }
+    }
+
+    #[test]
+    fn test_store_wraps_around_memory() {
+        with_processor(&[], |processor| {
+            processor.registers = [14, 0x1234_5678, 0, 0];
+            processor.store(0, 1);
+            assert_eq!(processor.main_memory[14..], [0x78, 0x56]);
+            assert_eq!(processor.main_memory[..2], [0x34, 0x12]);
+        });
+    }
+
+    #[test]
+    fn test_run_finished() {
+        // inc r0, inc r0, inc r1
+        with_processor(&[&[0x18, 0x18, 0x19]], |processor| {
+            let execution = processor.run(100);
+            assert_eq!(
+                execution,
+                Execution {
+                    outcome: ExecutionOutcome::Finished,
+                    steps: 3
+                }
+            );
+            assert_eq!(processor.registers, [2, 1, 0, 0]);
+        });
+    }
+
+    #[test]
+    fn test_run_empty() {
+        with_processor(&[], |processor| {
+            let execution = processor.run(100);
+            assert_eq!(
+                execution,
+                Execution {
+                    outcome: ExecutionOutcome::Finished,
+                    steps: 0
+                }
+            );
+        });
+        with_processor(&[&[]], |processor| {
+            let execution = processor.run(100);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(execution.steps, 0);
+        });
+    }
+
+    #[test]
+    fn test_run_finished_at_end_of_block() {
+        // inc r0, block end, inc r0
+        with_processor(&[&[0x18, 0x00, 0x18]], |processor| {
+            let execution = processor.run(100);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(execution.steps, 1);
+            assert_eq!(processor.registers, [1, 0, 0, 0]);
+        });
+    }
+
+    #[test]
+    fn test_run_out_of_fuel() {
+        with_processor(&[&[0x18; 10]], |processor| {
+            let execution = processor.run(4);
+            assert_eq!(
+                execution,
+                Execution {
+                    outcome: ExecutionOutcome::OutOfFuel,
+                    steps: 4
+                }
+            );
+            assert_eq!(processor.registers, [4, 0, 0, 0]);
+            // we can resume with more fuel
+            let execution = processor.run(100);
+            assert_eq!(
+                execution,
+                Execution {
+                    outcome: ExecutionOutcome::Finished,
+                    steps: 6
+                }
+            );
+            assert_eq!(processor.registers, [10, 0, 0, 0]);
+        });
+    }
+
+    #[test]
+    fn test_run_exactly_enough_fuel() {
+        with_processor(&[&[0x18; 4]], |processor| {
+            let execution = processor.run(4);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(execution.steps, 4);
+        });
+    }
+
+    #[test]
+    fn test_run_stack_overflow() {
+        // push r0 repeatedly
+        with_processor(&[&[0x10; MAX_STACK_SIZE + 10]], |processor| {
+            let execution = processor.run(1000);
+            assert_eq!(
+                execution,
+                Execution {
+                    outcome: ExecutionOutcome::StackOverflow,
+                    steps: MAX_STACK_SIZE as u64 + 1
+                }
+            );
+            assert_eq!(processor.stack.len(), MAX_STACK_SIZE);
+        });
+    }
+
+    #[test]
+    fn test_run_returned_from_entry_block() {
+        // inc r0, return, inc r0
+        with_processor(&[&[0x18, 0x02, 0x18]], |processor| {
+            let execution = processor.run(100);
+            assert_eq!(
+                execution,
+                Execution {
+                    outcome: ExecutionOutcome::ReturnedFromEntryBlock,
+                    steps: 2
+                }
+            );
+            assert_eq!(processor.registers, [1, 0, 0, 0]);
+        });
+    }
+
+    // the pattern 0x1234_5678 as pattern bytes
+    const PATTERN: [u8; 8] = [0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8];
+
+    fn concat(parts: &[&[u8]]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    #[test]
+    fn test_call() {
+        let code = concat(&[
+            // entry block: inc r0, call 0x1234_5678, inc r2
+            &[0x18],
+            &PATTERN,
+            &[0x01, 0x1A, 0x00],
+            // called block, identified by 0x1234_5678: inc r1
+            &PATTERN,
+            &[0x19],
+        ]);
+        with_processor(&[&code], |processor| {
+            let execution = processor.run(100);
+            assert_eq!(
+                execution,
+                Execution {
+                    outcome: ExecutionOutcome::Finished,
+                    // the pattern bytes are executed as no-ops
+                    steps: 20
+                }
+            );
+            assert_eq!(processor.registers, [1, 1, 1, 0]);
+            assert!(processor.call_stack.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_call_return() {
+        let code = concat(&[
+            // entry block: call 0x1234_5678, inc r2
+            &[0x18],
+            &PATTERN,
+            &[0x01, 0x1A, 0x00],
+            // called block: inc r1, return, inc r3
+            &PATTERN,
+            &[0x19, 0x02, 0x1B],
+        ]);
+        with_processor(&[&code], |processor| {
+            let execution = processor.run(100);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(processor.registers, [1, 1, 1, 0]);
+        });
+    }
+
+    #[test]
+    fn test_call_no_match() {
+        let code = concat(&[
+            &[0x18],
+            &PATTERN,
+            &[0x01, 0x1A, 0x00],
+            // this block is identified by 0x1234_5679
+            &[0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF9],
+            &[0x19],
+        ]);
+        with_processor(&[&code], |processor| {
+            let execution = processor.run(100);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(processor.registers, [1, 0, 1, 0]);
+        });
+    }
+
+    #[test]
+    fn test_call_other_strand() {
+        let entry = concat(&[&[0x18], &PATTERN, &[0x01, 0x1A, 0x00, 0x1B]]);
+        // the matching block has block index 1 in the second strand
+        let other = concat(&[&[0x1B, 0x00], &PATTERN, &[0x19]]);
+        with_processor(&[&entry, &other], |processor| {
+            let execution = processor.run(100);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(processor.registers, [1, 1, 1, 0]);
+        });
+    }
+
+    #[test]
+    fn test_call_does_not_look_at_earlier_blocks() {
+        let code = concat(&[
+            // block 0 is identified by the pattern
+            &PATTERN,
+            &[0x18, 0x00],
+            // block 1 calls the pattern, which only matches block 0
+            &[0x19],
+            &PATTERN,
+            &[0x01, 0x00],
+            // block 2 doesn't match
+            &[0x1A],
+        ]);
+        with_processor(&[&code], |processor| {
+            // start executing in block 1
+            processor.block = BlockId::new(0, 1);
+            processor.pc = processor.block_start(&processor.block);
+            let execution = processor.run(100);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(processor.registers, [0, 1, 0, 0]);
+        });
+    }
+
+    #[test]
+    fn test_call_depth_exceeded() {
+        // the entry block calls itself
+        let code = concat(&[&PATTERN, &[0x01]]);
+        with_processor(&[&code], |processor| {
+            let execution = processor.run(10_000);
+            assert_eq!(execution.outcome, ExecutionOutcome::CallDepthExceeded);
+            assert_eq!(processor.call_stack.len(), MAX_CALL_DEPTH);
+        });
+    }
+
+    #[test]
+    fn test_if() {
+        // if r0, inc r1, inc r2
+        with_processor(&[&[0x04, 0x19, 0x1A]], |processor| {
+            processor.registers = [1, 0, 0, 0];
+            let execution = processor.run(100);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(execution.steps, 3);
+            assert_eq!(processor.registers, [1, 1, 1, 0]);
+        });
+        with_processor(&[&[0x04, 0x19, 0x1A]], |processor| {
+            let execution = processor.run(100);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(execution.steps, 2);
+            assert_eq!(processor.registers, [0, 0, 1, 0]);
+        });
+    }
+
+    #[test]
+    fn test_if_at_end_of_block() {
+        // if r0, block end, inc r1
+        with_processor(&[&[0x04, 0x00, 0x19]], |processor| {
+            let execution = processor.run(100);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(processor.registers, [0, 0, 0, 0]);
+        });
+    }
+
+    #[test]
+    fn test_if_nested() {
+        // if r0, if r1, inc r2, inc r3
+        let code = [0x04, 0x05, 0x1A, 0x1B];
+        for (registers, expected) in [
+            ([0, 0, 0, 0], [0, 0, 0, 1]),
+            ([1, 0, 0, 0], [1, 0, 0, 1]),
+            ([0, 1, 0, 0], [0, 1, 0, 1]),
+            ([1, 1, 0, 0], [1, 1, 1, 1]),
+        ] {
+            with_processor(&[&code], |processor| {
+                processor.registers = registers;
+                processor.run(100);
+                assert_eq!(processor.registers, expected);
+            });
+        }
+    }
+
+    #[test]
+    fn test_if_skips_call() {
+        let code = concat(&[
+            // if r0, call 0x1234_5678, inc r2
+            &[0x04],
+            &PATTERN,
+            &[0x01, 0x1A, 0x00],
+            &PATTERN,
+            &[0x19],
+        ]);
+        with_processor(&[&code], |processor| {
+            processor.run(100);
+            assert_eq!(processor.registers, [0, 0, 1, 0]);
+        });
+        with_processor(&[&code], |processor| {
+            processor.registers = [1, 0, 0, 0];
+            processor.run(100);
+            assert_eq!(processor.registers, [1, 1, 1, 0]);
+        });
+    }
+
+    #[test]
+    fn test_repeat() {
+        // repeat r0, inc r1, inc r2
+        for (count, expected) in [(0, 0), (1, 1), (3, 3)] {
+            with_processor(&[&[0x08, 0x19, 0x1A]], |processor| {
+                processor.registers = [count, 0, 0, 0];
+                let execution = processor.run(100);
+                assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+                assert_eq!(execution.steps, 2 + expected as u64);
+                assert_eq!(processor.registers, [count, expected, 1, 0]);
+                assert!(processor.loops.is_empty());
+            });
+        }
+    }
+
+    #[test]
+    fn test_repeat_count_read_once() {
+        // repeat r0, dec r0
+        with_processor(&[&[0x08, 0x1C]], |processor| {
+            processor.registers = [3, 0, 0, 0];
+            processor.run(100);
+            assert_eq!(processor.registers, [0, 0, 0, 0]);
+        });
+    }
+
+    #[test]
+    fn test_repeat_saturates() {
+        // repeat r0, inc r1
+        with_processor(&[&[0x08, 0x19]], |processor| {
+            processor.registers = [u32::MAX, 0, 0, 0];
+            let execution = processor.run(10_000);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(processor.registers, [u32::MAX, MAX_REPEAT_COUNT, 0, 0]);
+        });
+    }
+
+    #[test]
+    fn test_repeat_at_end_of_block() {
+        // repeat r0, block end
+        with_processor(&[&[0x08]], |processor| {
+            processor.registers = [3, 0, 0, 0];
+            let execution = processor.run(100);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(execution.steps, 1);
+            assert!(processor.loops.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_repeat_nested() {
+        // repeat r0, repeat r1, inc r2, inc r3
+        with_processor(&[&[0x08, 0x09, 0x1A, 0x1B]], |processor| {
+            processor.registers = [3, 4, 0, 0];
+            let execution = processor.run(100);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(processor.registers, [3, 4, 12, 1]);
+        });
+        with_processor(&[&[0x08, 0x09, 0x1A, 0x1B]], |processor| {
+            processor.registers = [3, 0, 0, 0];
+            processor.run(100);
+            assert_eq!(processor.registers, [3, 0, 0, 1]);
+        });
+    }
+
+    #[test]
+    fn test_repeat_if() {
+        // repeat r0, if r1, inc r2, inc r3
+        with_processor(&[&[0x08, 0x05, 0x1A, 0x1B]], |processor| {
+            processor.registers = [3, 1, 0, 0];
+            processor.run(100);
+            assert_eq!(processor.registers, [3, 1, 3, 1]);
+        });
+        with_processor(&[&[0x08, 0x05, 0x1A, 0x1B]], |processor| {
+            processor.registers = [3, 0, 0, 0];
+            let execution = processor.run(100);
+            assert_eq!(execution.steps, 5);
+            assert_eq!(processor.registers, [3, 0, 0, 1]);
+        });
+    }
+
+    #[test]
+    fn test_repeat_skips_pattern_bytes() {
+        // repeat r0, pattern bytes, inc r1, inc r2
+        with_processor(&[&[0x08, 0xF1, 0xF2, 0x19, 0x1A]], |processor| {
+            processor.registers = [2, 0, 0, 0];
+            processor.run(100);
+            assert_eq!(processor.registers, [2, 2, 1, 0]);
+        });
+    }
+
+    #[test]
+    fn test_repeat_call() {
+        let code = concat(&[
+            // repeat r0, call 0x1234_5678, inc r2
+            &[0x08],
+            &PATTERN,
+            &[0x01, 0x1A, 0x00],
+            // the called block ends with a repeat of nothing
+            &PATTERN,
+            &[0x19, 0x08],
+        ]);
+        with_processor(&[&code], |processor| {
+            processor.registers = [3, 0, 0, 0];
+            let execution = processor.run(100);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(processor.registers, [3, 3, 1, 0]);
+            assert!(processor.loops.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_repeat_in_called_block_abandoned_on_return() {
+        let code = concat(&[
+            // call 0x1234_5678, inc r2
+            &[0x18],
+            &PATTERN,
+            &[0x01, 0x1A, 0x00],
+            // the called block repeats a return
+            &PATTERN,
+            &[0x09, 0x02, 0x1B],
+        ]);
+        with_processor(&[&code], |processor| {
+            processor.registers = [0, 3, 0, 0];
+            let execution = processor.run(100);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(processor.registers, [1, 3, 1, 0]);
+            assert!(processor.loops.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_value() {
+        // value r2 42, inc r2
+        with_processor(&[&[0xFA, 0xFA, 0x03, 0x1A]], |processor| {
+            let execution = processor.run(100);
+            assert_eq!(execution.outcome, ExecutionOutcome::Finished);
+            assert_eq!(processor.registers, [0, 0, 43, 0]);
+        });
+    }
+
+    #[test]
+    fn test_value_full_pattern() {
+        let code = concat(&[&[0x18], &PATTERN, &[0x03]]);
+        with_processor(&[&code], |processor| {
+            processor.run(100);
+            assert_eq!(processor.registers, [0x1234_5678 >> 2, 0, 0, 0]);
+        });
+    }
+
+    #[test]
+    fn test_value_from_instructions() {
+        // inc r0, value: the pattern is made up by the inc instruction
+        with_processor(&[&[0x18, 0x03]], |processor| {
+            processor.run(100);
+            assert_eq!(processor.registers, [0x18 >> 2, 0, 0, 0]);
+        });
+    }
+
+    proptest! {
+        // any bytes are a valid program: running them never panics, and
+        // the processor stays within its limits
+        #[test]
+        fn test_run_arbitrary_bytes(
+            strands in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..256), 0..4),
+            registers in any::<[u32; 4]>(),
+            max_distance in 0..=32u32,
+            match_chance in 0.0..=1.0f64,
+            seed in any::<u64>(),
+            linking in prop_oneof![
+                Just(Linking::Dynamic),
+                Just(Linking::Stochastic),
+                Just(Linking::Deterministic),
+            ],
+        ) {
+            let strands = strands.iter().map(|bytes| &bytes[..]).collect::<Vec<_>>();
+            let program = link_bytes(&strands, max_distance, match_chance, linking);
+            let mut main_memory = [0; 64];
+            let mut processor =
+                Processor::new(&program, &mut main_memory, SmallRng::seed_from_u64(seed));
+            processor.registers = registers;
+            let execution = processor.run(1_000);
+            prop_assert!(execution.steps <= 1_000);
+            prop_assert!(processor.stack.len() <= MAX_STACK_SIZE);
+            prop_assert!(processor.call_stack.len() <= MAX_CALL_DEPTH);
+            prop_assert!(processor.pc <= processor.program.instruction_memory.len());
+        }
+
+        #[test]
+        fn test_run_compiled_arbitrary_bytes(
+            strands in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..256), 0..4),
+            registers in any::<[u32; 4]>(),
+            match_chance in 0.0..=1.0f64,
+            seed in any::<u64>(),
+            linking in prop_oneof![
+                Just(Linking::Dynamic),
+                Just(Linking::Stochastic),
+                Just(Linking::Deterministic),
+            ],
+            fuel in 0..1_000u64,
+        ) {
+            let strands = strands.iter().map(|bytes| &bytes[..]).collect::<Vec<_>>();
+            let program = link_bytes(&strands, 8, match_chance, linking);
+            let mut bytes_memory = [0; 64];
+            let mut bytes_processor =
+                Processor::new(&program, &mut bytes_memory, SmallRng::seed_from_u64(seed));
+            bytes_processor.registers = registers;
+            let mut compiled_memory = [0; 64];
+            let mut compiled_processor =
+                Processor::new(&program, &mut compiled_memory, SmallRng::seed_from_u64(seed));
+            compiled_processor.registers = registers;
+            // run in two parts, to check resuming
+            for _ in 0..2 {
+                prop_assert_eq!(bytes_processor.run(fuel), compiled_processor.run_compiled(fuel));
+                prop_assert_eq!(bytes_processor.registers, compiled_processor.registers);
+                prop_assert_eq!(bytes_processor.pc, compiled_processor.pc);
+                prop_assert_eq!(&bytes_processor.stack, &compiled_processor.stack);
+            }
+            drop(bytes_processor);
+            drop(compiled_processor);
+            prop_assert_eq!(bytes_memory, compiled_memory);
+        }
+    }
 }
@@ -0,0 +1,308 @@
+// crossover operators that breed a child genome from two parents
+//
+// like mutations, crossover can cut genomes anywhere, because any bytes
+// are a valid genome. the block aware operators cut at zero bytes instead,
+// so that blocks travel whole, with the pattern that identifies them.
+//
+// the child has the strands of the first parent. a strand is crossed with
+// the strand of the second parent with the same id, and strands the
+// second parent doesn't have are copied from the first.
+
+use std::fmt::Debug;
+
+use rand::{Rng, RngCore};
+
+use crate::blockid::BlockId;
+use crate::fuzzy::FuzzyBitMap;
+use crate::genome::Genome;
+
+/// A crossover breeds a child from two parents.
+pub trait Crossover: Debug + Send + Sync {
+    fn cross(&self, first: &Genome, second: &Genome, rng: &mut dyn RngCore) -> Genome;
+}
+
+/// Cut each strand of both parents at a random point, and join the start
+/// of the first to the end of the second. The points are picked for each
+/// parent, so the child can be shorter or longer than its parents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OnePointCrossover;
+
+impl Crossover for OnePointCrossover {
+    fn cross(&self, first: &Genome, second: &Genome, rng: &mut dyn RngCore) -> Genome {
+        cross_strands(first, second, |first, second| {
+            let cut = rng.gen_range(0..=first.len());
+            let other_cut = rng.gen_range(0..=second.len());
+            [&first[..cut], &second[other_cut..]].concat()
+        })
+    }
+}
+
+/// Replace a random range of each strand of the first parent with a
+/// random range of the second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TwoPointCrossover;
+
+impl Crossover for TwoPointCrossover {
+    fn cross(&self, first: &Genome, second: &Genome, rng: &mut dyn RngCore) -> Genome {
+        cross_strands(first, second, |first, second| {
+            let (start, end) = random_range(first.len(), rng);
+            let (other_start, other_end) = random_range(second.len(), rng);
+            [
+                &first[..start],
+                &second[other_start..other_end],
+                &first[end..],
+            ]
+            .concat()
+        })
+    }
+}
+
+// a random range in 0..=len
+fn random_range(len: usize, rng: &mut dyn RngCore) -> (usize, usize) {
+    let a = rng.gen_range(0..=len);
+    let b = rng.gen_range(0..=len);
+    (a.min(b), a.max(b))
+}
+
+/// Replace blocks of the first parent with the blocks of the second
+/// parent at the same index in the same strand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockCrossover {
+    /// The chance that a block is replaced.
+    pub rate: f64,
+}
+
+impl BlockCrossover {
+    pub fn new(rate: f64) -> BlockCrossover {
+        assert!((0.0..=1.0).contains(&rate));
+        BlockCrossover { rate }
+    }
+}
+
+impl Crossover for BlockCrossover {
+    fn cross(&self, first: &Genome, second: &Genome, rng: &mut dyn RngCore) -> Genome {
+        let strands = (0..first.strands().len())
+            .map(|strand_id| {
+                let blocks = (0..first.block_count(strand_id)).map(|block_index| {
+                    let replace = strand_id < second.strands().len()
+                        && block_index < second.block_count(strand_id)
+                        && rng.gen_bool(self.rate);
+                    let parent = if replace { second } else { first };
+                    parent.block(strand_id, block_index)
+                });
+                join_blocks(blocks)
+            })
+            .collect();
+        Genome::new(strands)
+    }
+}
+
+/// Replace blocks of the first parent with the blocks of the second
+/// parent that have the closest pattern, wherever they are. Blocks with
+/// patterns within a hamming distance do the same job, as calls find
+/// either of them, so this swaps blocks that are alike.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HomologousCrossover {
+    /// The maximum hamming distance between the patterns of blocks that
+    /// are paired.
+    pub max_distance: u32,
+    /// The chance that a block with a homologous block is replaced.
+    pub rate: f64,
+}
+
+impl HomologousCrossover {
+    pub fn new(max_distance: u32, rate: f64) -> HomologousCrossover {
+        assert!((0.0..=1.0).contains(&rate));
+        HomologousCrossover { max_distance, rate }
+    }
+}
+
+impl Crossover for HomologousCrossover {
+    fn cross(&self, first: &Genome, second: &Genome, rng: &mut dyn RngCore) -> Genome {
+        let mut homologs = FuzzyBitMap::new(self.max_distance, 1.0);
+        for (strand_id, strand) in second.strand_views().iter().enumerate() {
+            for (block_index, block) in strand.blocks().iter().enumerate() {
+                homologs.insert(block.pattern().get(), BlockId::new(strand_id, block_index));
+            }
+        }
+        let strands = first
+            .strand_views()
+            .iter()
+            .map(|strand| {
+                let blocks = strand.blocks().iter().map(|block| {
+                    let homolog = homologs.lookup_best(block.pattern().get(), 0).found;
+                    match homolog {
+                        Some(found) if rng.gen_bool(self.rate) => {
+                            let block_id = found.value;
+                            second.block(block_id.strand_id(), block_id.block_index())
+                        }
+                        _ => block.slice(),
+                    }
+                });
+                join_blocks(blocks)
+            })
+            .collect();
+        Genome::new(strands)
+    }
+}
+
+// cross the strands of both parents with the same id, and copy the
+// strands only the first parent has
+fn cross_strands(
+    first: &Genome,
+    second: &Genome,
+    mut cross: impl FnMut(&[u8], &[u8]) -> Vec<u8>,
+) -> Genome {
+    let strands = first
+        .strands()
+        .iter()
+        .enumerate()
+        .map(
+            |(strand_id, strand)| match second.strands().get(strand_id) {
+                Some(other) => cross(strand, other),
+                None => strand.clone(),
+            },
+        )
+        .collect();
+    Genome::new(strands)
+}
+
+fn join_blocks<'a>(blocks: impl Iterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (block_index, block) in blocks.enumerate() {
+        if block_index > 0 {
+            bytes.push(0);
+        }
+        bytes.extend_from_slice(block);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use crate::machine::Machine;
+
+    use super::*;
+
+    fn parents() -> (Genome, Genome) {
+        let first = Genome::new(vec![vec![0x10, 0x11, 0x12, 0x13], vec![0x14, 0x00, 0x15]]);
+        let second = Genome::new(vec![vec![0x20, 0x21, 0x22]]);
+        (first, second)
+    }
+
+    fn rng() -> SmallRng {
+        SmallRng::from_seed([0; 32])
+    }
+
+    #[test]
+    fn test_one_point() {
+        let (first, second) = parents();
+        let mut rng = rng();
+        for _ in 0..16 {
+            let child = OnePointCrossover.cross(&first, &second, &mut rng);
+            assert_eq!(child.strands().len(), 2);
+            // a start of the first strand and an end of the other
+            let strand = &child.strands()[0];
+            let cut = strand.iter().take_while(|&&byte| byte < 0x20).count();
+            assert!(first.strands()[0].starts_with(&strand[..cut]));
+            assert!(second.strands()[0].ends_with(&strand[cut..]));
+            // the second parent has no strand to cross with
+            assert_eq!(child.strands()[1], first.strands()[1]);
+        }
+    }
+
+    #[test]
+    fn test_two_point() {
+        let (first, second) = parents();
+        let mut rng = rng();
+        for _ in 0..16 {
+            let child = TwoPointCrossover.cross(&first, &second, &mut rng);
+            // the bytes of the second parent are all in the middle
+            let strand = &child.strands()[0];
+            let start = strand.iter().take_while(|&&byte| byte < 0x20).count();
+            let middle = strand[start..]
+                .iter()
+                .take_while(|&&byte| byte >= 0x20)
+                .count();
+            let (start, end) = (start.min(strand.len()), start + middle);
+            assert!(first.strands()[0].starts_with(&strand[..start]));
+            assert!(first.strands()[0].ends_with(&strand[end..]));
+            assert!(
+                middle == 0
+                    || second.strands()[0]
+                        .windows(middle)
+                        .any(|window| window == &strand[start..end])
+            );
+            assert!(strand[end..].iter().all(|&byte| byte < 0x20));
+        }
+    }
+
+    #[test]
+    fn test_block_crossover() {
+        let first = Genome::new(vec![vec![0x10, 0x00, 0x11, 0x00, 0x12], vec![0x13]]);
+        let second = Genome::new(vec![vec![0x20, 0x00, 0x21]]);
+        let child = BlockCrossover::new(0.0).cross(&first, &second, &mut rng());
+        assert_eq!(child, first);
+        let child = BlockCrossover::new(1.0).cross(&first, &second, &mut rng());
+        // the third block and the second strand have nothing to swap with
+        assert_eq!(
+            child.strands(),
+            &[vec![0x20, 0x00, 0x21, 0x00, 0x12], vec![0x13]]
+        );
+    }
+
+    #[test]
+    fn test_homologous_crossover() {
+        let first =
+            Genome::assemble(&["inc r0\nblock @0x0000_0003\ninc r1\nblock @0xffff_0000"]).unwrap();
+        let second =
+            Genome::assemble(&["inc r2", "block @0x0000_0007\ninc r3\nblock @0x0f0f_0f0f"])
+                .unwrap();
+        let child = HomologousCrossover::new(1, 1.0).cross(&first, &second, &mut rng());
+        assert_eq!(child.block_count(0), first.block_count(0));
+        // the blocks without a pattern are homologs, the first one found is
+        // the closest
+        assert_eq!(child.block(0, 0), second.block(0, 0));
+        // the block with pattern 3 has a homolog one bit away, and the
+        // block with pattern 0xffff_0000 has none
+        assert_eq!(child.block(0, 1), second.block(1, 0));
+        assert_eq!(child.block(0, 2), first.block(0, 2));
+        let child = HomologousCrossover::new(1, 0.0).cross(&first, &second, &mut rng());
+        assert_eq!(child, first);
+    }
+
+    #[test]
+    fn test_same_seed() {
+        let (first, second) = parents();
+        assert_eq!(
+            TwoPointCrossover.cross(&first, &second, &mut rng()),
+            TwoPointCrossover.cross(&first, &second, &mut rng())
+        );
+    }
+
+    fn genome() -> impl Strategy<Value = Genome> {
+        prop::collection::vec(prop::collection::vec(any::<u8>(), 0..32), 1..4).prop_map(Genome::new)
+    }
+
+    proptest! {
+        #[test]
+        fn test_children_run(first in genome(), second in genome(), seed in any::<u8>()) {
+            let crossovers: [&dyn Crossover; 4] = [
+                &OnePointCrossover,
+                &TwoPointCrossover,
+                &BlockCrossover::new(0.5),
+                &HomologousCrossover::new(4, 0.5),
+            ];
+            let mut rng = SmallRng::from_seed([seed; 32]);
+            for crossover in crossovers {
+                let child = crossover.cross(&first, &second, &mut rng);
+                prop_assert_eq!(child.strands().len(), first.strands().len());
+                Machine::new().fuel(1000).run(&child, &[1, 2]);
+            }
+        }
+    }
+}
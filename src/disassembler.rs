@@ -0,0 +1,302 @@
+// a disassembler that turns strands back into the assembly language
+//
+// each block gets a block header with its block id and its pattern in a
+// comment. pattern bytes before a call or value are folded into it when
+// the assembler would produce them, so that the output assembles to the
+// same bytes again. calls can be annotated with the block they resolve to.
+
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+
+use crate::blockid::BlockId;
+use crate::blockpattern::{is_pattern_byte, BlockPattern};
+use crate::instruction::{BlockRef, Instruction};
+use crate::structure::{Blocks, Strand};
+
+// the amount of pattern bytes the assembler produces for a pattern
+const PATTERN_BYTES: usize = 8;
+
+/// How calls are resolved in the disassembly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Calls are not resolved.
+    Unresolved,
+    /// Calls resolve to the best matching block.
+    Deterministic,
+    /// Calls resolve as they do during execution, using a rng with this
+    /// seed.
+    Seeded(u64),
+}
+
+pub(crate) fn disassemble(strands: &[Strand], blocks: &Blocks, resolution: Resolution) -> String {
+    let rng = match resolution {
+        Resolution::Seeded(seed) => Some(SmallRng::seed_from_u64(seed)),
+        _ => None,
+    };
+    let mut disassembler = Disassembler {
+        strands,
+        blocks,
+        resolution,
+        rng,
+        lines: Vec::new(),
+    };
+    for (strand_id, strand) in strands.iter().enumerate() {
+        disassembler.lines.push(format!("; strand {}", strand_id));
+        for (block_index, block) in strand.blocks().iter().enumerate() {
+            disassembler.block(
+                BlockId::new(strand_id, block_index),
+                block.pattern(),
+                block.slice(),
+            );
+        }
+    }
+    let mut text = disassembler.lines.join("\n");
+    text.push('\n');
+    text
+}
+
+struct Disassembler<'a> {
+    strands: &'a [Strand<'a>],
+    blocks: &'a Blocks,
+    resolution: Resolution,
+    rng: Option<SmallRng>,
+    lines: Vec<String>,
+}
+
+impl<'a> Disassembler<'a> {
+    fn block(&mut self, block_id: BlockId, pattern: &BlockPattern, slice: &[u8]) {
+        // fold the leading pattern bytes into the header if the assembler
+        // would produce them
+        let start = if slice.len() >= PATTERN_BYTES
+            && slice[..PATTERN_BYTES].iter().all(|&b| is_pattern_byte(b))
+        {
+            self.lines.push(format!("block {} ; {}", pattern, block_id));
+            PATTERN_BYTES
+        } else {
+            self.lines.push(format!("block ; {} {}", block_id, pattern));
+            0
+        };
+        // pattern bytes we haven't written yet
+        let mut pending = start;
+        for index in start..slice.len() {
+            if is_pattern_byte(slice[index]) {
+                continue;
+            }
+            let instruction = Instruction::decode(slice, index);
+            let folded = matches!(instruction, Instruction::Call(_) | Instruction::Value(_, _))
+                && index - pending >= PATTERN_BYTES;
+            let unfolded_end = if folded { index - PATTERN_BYTES } else { index };
+            self.pattern_bytes(&slice[pending..unfolded_end]);
+            pending = index + 1;
+            let line = match instruction {
+                Instruction::Call(BlockRef::Pattern(call_pattern)) => {
+                    // the pattern the call looks for may have wildcards
+                    let pattern = self.blocks.call_pattern(slice, index);
+                    let resolved = self.resolve(pattern, block_id.block_index());
+                    if folded && resolved.is_empty() {
+                        format!("call {}", call_pattern)
+                    } else if folded {
+                        format!("call {} ;{}", call_pattern, resolved)
+                    } else {
+                        format!("call ; {}{}", call_pattern, resolved)
+                    }
+                }
+                Instruction::Value(r, value) if !folded => format!("value ; {} {}", r, value),
+                instruction => instruction.to_string(),
+            };
+            self.lines.push(format!("    {}", line));
+        }
+        self.pattern_bytes(&slice[pending..]);
+    }
+
+    fn pattern_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.lines
+                .push(format!("    {}", Instruction::decode(&[byte], 0)));
+        }
+    }
+
+    fn resolve(&mut self, pattern: BlockPattern, block_index: usize) -> String {
+        let target = match (self.resolution, &mut self.rng) {
+            (Resolution::Unresolved, _) => return String::new(),
+            (Resolution::Seeded(_), Some(rng)) => self.blocks.lookup(pattern, block_index, rng),
+            _ => self.blocks.lookup_best(pattern, block_index),
+        };
+        match target {
+            Some(target) => {
+                let target_pattern =
+                    self.strands[target.strand_id()].blocks()[target.block_index()].pattern();
+                format!(
+                    " -> {} distance {}",
+                    target,
+                    self.blocks.distance(pattern, *target_pattern)
+                )
+            }
+            None => " -> none".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::assembler::assemble;
+
+    use super::*;
+
+    fn disassemble_bytes(strands: &[&[u8]], resolution: Resolution) -> String {
+        let strands = strands
+            .iter()
+            .map(|bytes| Strand::from_bytes(bytes))
+            .collect::<Vec<_>>();
+        let blocks = Blocks::new(2, 0.5, &strands);
+        disassemble(&strands, &blocks, resolution)
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let bytes = assemble(
+            "
+            inc r0
+            call @0x5a5a_1234
+            block @0x5a5a_1234
+            value r2 42
+            add r0 r2
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            disassemble_bytes(&[&bytes], Resolution::Deterministic),
+            "\
+; strand 0
+block ; 0:0 @0x85a5_a123
+    inc r0
+    call @0x5a5a_1234 ; -> 0:1 distance 0
+block @0x5a5a_1234 ; 0:1
+    value r2 42
+    add r0 r2
+"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_bare() {
+        // the pattern of the call and the value are made up by instructions
+        let bytes = [0x18, 0xF2, 0x01, 0x19, 0x03, 0xF1];
+        assert_eq!(
+            disassemble_bytes(&[&bytes], Resolution::Unresolved),
+            "\
+; strand 0
+block ; 0:0 @0x8201_1931
+    inc r0
+    pattern 0x2
+    call ; @0x0000_0082
+    inc r1
+    value ; r1 395334
+    pattern 0x1
+"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_unfolded_pattern_bytes() {
+        // nine pattern bytes before the call, the first is not folded
+        let bytes = [
+            0x18, 0xFF, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0x01,
+        ];
+        assert_eq!(
+            disassemble_bytes(&[&bytes], Resolution::Unresolved),
+            "\
+; strand 0
+block ; 0:0 @0x8f12_3456
+    inc r0
+    pattern 0xf
+    call @0x1234_5678
+"
+        );
+    }
+
+    #[test]
+    fn test_disassemble_resolution() {
+        let entry = assemble("inc r0\ncall @0x0000_0003\nblock @0x0000_0001").unwrap();
+        let other = assemble("block\nblock @0x0000_0000").unwrap();
+        assert_eq!(
+            disassemble_bytes(&[&entry, &other], Resolution::Deterministic),
+            "\
+; strand 0
+block ; 0:0 @0x8000_0000
+    inc r0
+    call @0x0000_0003 ; -> 0:1 distance 1
+block @0x0000_0001 ; 0:1
+; strand 1
+block ; 1:0 @0x0000_0000
+block @0x0000_0000 ; 1:1
+"
+        );
+        // with randomness we may pick another candidate, but the same
+        // seed gives the same result
+        let seeded = disassemble_bytes(&[&entry, &other], Resolution::Seeded(1));
+        assert_eq!(
+            seeded,
+            disassemble_bytes(&[&entry, &other], Resolution::Seeded(1))
+        );
+        assert!(
+            seeded.contains("-> 0:1 distance 1")
+                || seeded.contains("-> 1:0 distance 2")
+                || seeded.contains("-> 1:1 distance 2")
+                || seeded.contains("-> none")
+        );
+    }
+
+    #[test]
+    fn test_disassemble_no_match() {
+        let bytes = assemble("inc r0\ncall @0xffff_ffff\nblock @0x0000_0000").unwrap();
+        assert!(disassemble_bytes(&[&bytes], Resolution::Deterministic)
+            .contains("call @0xffff_ffff ; -> none"));
+    }
+
+    #[test]
+    fn test_disassemble_block_pattern_used_by_call() {
+        // the pattern bytes are folded into the block header, so
+        // the call takes its pattern from the same bytes
+        let bytes = assemble("block @0x0000_0003\ncall").unwrap();
+        let text = disassemble_bytes(&[&bytes], Resolution::Deterministic);
+        assert_eq!(
+            text,
+            "\
+; strand 0
+block @0x0000_0003 ; 0:0
+    call ; @0x0000_0003 -> 0:0 distance 0
+"
+        );
+        assert_eq!(assemble(&text).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_disassemble_empty_blocks() {
+        let bytes = [0x00, 0x18, 0x00];
+        let text = disassemble_bytes(&[&bytes], Resolution::Unresolved);
+        assert_eq!(
+            text,
+            "\
+; strand 0
+block ; 0:0 @0x0000_0000
+block ; 0:1 @0x1800_0000
+    inc r0
+block ; 0:2 @0x0000_0000
+"
+        );
+        assert_eq!(assemble(&text).unwrap(), bytes);
+    }
+
+    proptest! {
+        #[test]
+        fn test_disassemble_assemble_round_trip(
+            bytes in prop::collection::vec(any::<u8>(), 0..64),
+        ) {
+            let text = disassemble_bytes(&[&bytes], Resolution::Deterministic);
+            prop_assert_eq!(assemble(&text).unwrap(), bytes);
+        }
+    }
+}
@@ -0,0 +1,865 @@
+// evolution of a population of genomes
+//
+// an evolver breeds each generation from the one before: it selects
+// parents by their fitness, crosses and mutates them into children, and
+// keeps the best individuals unchanged as elites. steady state evolution
+// instead breeds one child at a time, which replaces an individual in the
+// population. all randomness comes from a single seeded rng, so a run with
+// the same seed, population and fitness evolves the same genomes.
+
+use rand::rngs::SmallRng;
+use rand::seq::index::sample;
+use rand::{Rng, RngCore, SeedableRng};
+
+use crate::crossover::Crossover;
+use crate::genome::Genome;
+use crate::mutation::{Mutation, Mutations};
+
+/// How fit a genome is. Higher is better. A fitness that is NaN counts as
+/// negative infinity, the least fit there is.
+pub trait Fitness {
+    fn fitness(&mut self, genome: &Genome) -> f64;
+}
+
+impl<F: FnMut(&Genome) -> f64> Fitness for F {
+    fn fitness(&mut self, genome: &Genome) -> f64 {
+        self(genome)
+    }
+}
+
+/// How parents are selected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Selection {
+    /// Pick this many individuals at random, and select the fittest.
+    Tournament(usize),
+    /// Select at random from this fraction of the fittest individuals.
+    Truncation(f64),
+    /// Select at random, with a chance proportional to how much fitter
+    /// an individual is than the least fit one. Individuals with a fitness
+    /// that isn't finite aren't selected, unless all are as fit.
+    FitnessProportional,
+}
+
+impl Default for Selection {
+    fn default() -> Selection {
+        Selection::Tournament(2)
+    }
+}
+
+/// Genomes, and their fitness once it is evaluated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Population {
+    genomes: Vec<Genome>,
+    fitness: Vec<Option<f64>>,
+}
+
+impl Population {
+    pub fn new(genomes: Vec<Genome>) -> Population {
+        let fitness = vec![None; genomes.len()];
+        Population { genomes, fitness }
+    }
+
+    pub fn len(&self) -> usize {
+        self.genomes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.genomes.is_empty()
+    }
+
+    pub fn genomes(&self) -> &[Genome] {
+        &self.genomes
+    }
+
+    /// The fitness of each genome, if it is evaluated.
+    pub fn fitness(&self) -> &[Option<f64>] {
+        &self.fitness
+    }
+
+    /// The fittest evaluated genome, and its fitness. Of equally fit
+    /// genomes this is the first.
+    pub fn best(&self) -> Option<(&Genome, f64)> {
+        self.genomes
+            .iter()
+            .zip(&self.fitness)
+            .filter_map(|(genome, fitness)| fitness.map(|fitness| (genome, fitness)))
+            .reduce(|best, other| if other.1 > best.1 { other } else { best })
+    }
+
+    // evaluate the fitness of the genomes that aren't evaluated yet
+    fn evaluate(&mut self, fitness: &mut impl Fitness) {
+        for (genome, evaluated) in self.genomes.iter().zip(&mut self.fitness) {
+            if evaluated.is_none() {
+                *evaluated = Some(evaluate(fitness, genome));
+            }
+        }
+    }
+
+    // the best, mean and worst fitness of an evaluated population
+    fn summary(&self) -> (f64, f64, f64) {
+        let fitness = self.fitness.iter().map(|fitness| fitness.unwrap());
+        (
+            fitness.clone().fold(f64::NEG_INFINITY, f64::max),
+            fitness.clone().sum::<f64>() / self.len() as f64,
+            fitness.fold(f64::INFINITY, f64::min),
+        )
+    }
+}
+
+// the fitness of a genome, where NaN is the least fit
+fn evaluate(fitness: &mut impl Fitness, genome: &Genome) -> f64 {
+    let value = fitness.fitness(genome);
+    if value.is_nan() {
+        f64::NEG_INFINITY
+    } else {
+        value
+    }
+}
+
+/// Statistics about an evaluated generation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GenerationStats {
+    /// The generation, where the initial population is generation 0.
+    pub generation: usize,
+    pub best: f64,
+    pub mean: f64,
+    pub worst: f64,
+    /// The mean amount of bytes in a genome.
+    pub mean_length: f64,
+}
+
+impl GenerationStats {
+    fn new(generation: usize, population: &Population) -> GenerationStats {
+        let (best, mean, worst) = population.summary();
+        let length = population.genomes.iter().map(Genome::len).sum::<usize>();
+        GenerationStats {
+            generation,
+            best,
+            mean,
+            worst,
+            mean_length: length as f64 / population.len() as f64,
+        }
+    }
+}
+
+/// An evolver runs generations of a population. It is configured with
+/// consuming methods:
+///
+/// ```
+/// use evoby::{Evolver, Genome, Machine, PointMutation, Population, Selection};
+///
+/// let mut machine = Machine::new().fuel(100);
+/// let mut evolver = Evolver::new(move |genome: &Genome| {
+///     machine.run(genome, &[]).registers[0] as f64
+/// })
+/// .selection(Selection::Tournament(3))
+/// .mutation(PointMutation::new(0.1))
+/// .elites(1)
+/// .seed(42);
+/// let mut population = Population::new(vec![Genome::from_bytes(&[0x18; 8]); 10]);
+/// let stats = evolver.run(&mut population, 5);
+/// assert_eq!(stats.len(), 6);
+/// ```
+pub struct Evolver<F> {
+    fitness: F,
+    selection: Selection,
+    elites: usize,
+    crossover: Option<Box<dyn Crossover>>,
+    crossover_rate: f64,
+    mutation: Box<dyn Mutation>,
+    rng: SmallRng,
+    generation: usize,
+}
+
+impl<F: Fitness> Evolver<F> {
+    pub fn new(fitness: F) -> Evolver<F> {
+        Evolver {
+            fitness,
+            selection: Selection::default(),
+            elites: 0,
+            crossover: None,
+            crossover_rate: 1.0,
+            mutation: Box::new(Mutations::new()),
+            rng: SmallRng::seed_from_u64(0),
+            generation: 0,
+        }
+    }
+
+    /// How parents are selected. By default this is a tournament of 2.
+    pub fn selection(mut self, selection: Selection) -> Evolver<F> {
+        match selection {
+            Selection::Tournament(size) => assert!(size > 0),
+            Selection::Truncation(fraction) => assert!(fraction > 0.0 && fraction <= 1.0),
+            Selection::FitnessProportional => {}
+        }
+        self.selection = selection;
+        self
+    }
+
+    /// The amount of fittest individuals that go to the next generation
+    /// unchanged. Their fitness isn't evaluated again.
+    pub fn elites(mut self, elites: usize) -> Evolver<F> {
+        self.elites = elites;
+        self
+    }
+
+    /// How two parents are crossed. Without a crossover, a child is a
+    /// mutated copy of a single parent.
+    pub fn crossover(mut self, crossover: impl Crossover + 'static) -> Evolver<F> {
+        self.crossover = Some(Box::new(crossover));
+        self
+    }
+
+    /// The chance that a child is bred by crossover rather than copied
+    /// from a single parent. By default this is 1.
+    pub fn crossover_rate(mut self, crossover_rate: f64) -> Evolver<F> {
+        assert!((0.0..=1.0).contains(&crossover_rate));
+        self.crossover_rate = crossover_rate;
+        self
+    }
+
+    /// How children are mutated. By default they aren't.
+    pub fn mutation(mut self, mutation: impl Mutation + 'static) -> Evolver<F> {
+        self.mutation = Box::new(mutation);
+        self
+    }
+
+    /// The seed for all randomness of evolution.
+    pub fn seed(mut self, seed: u64) -> Evolver<F> {
+        self.rng = SmallRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Evaluate the fitness of the genomes that aren't evaluated yet. The
+    /// population can't be empty.
+    pub fn evaluate(&mut self, population: &mut Population) -> GenerationStats {
+        assert!(!population.is_empty(), "the population is empty");
+        population.evaluate(&mut self.fitness);
+        GenerationStats::new(self.generation, population)
+    }
+
+    /// Breed the next generation of the population and evaluate it.
+    pub fn generation(&mut self, population: &mut Population) -> GenerationStats {
+        self.evaluate(population);
+        let fitness = population
+            .fitness
+            .iter()
+            .map(|fitness| fitness.unwrap())
+            .collect::<Vec<_>>();
+        // the indexes of the individuals, from the fittest to the least fit
+        let mut ranked = (0..population.len()).collect::<Vec<_>>();
+        ranked.sort_by(|&a, &b| fitness[b].total_cmp(&fitness[a]));
+
+        let elites = self.elites.min(population.len());
+        let mut genomes = Vec::with_capacity(population.len());
+        let mut next_fitness = Vec::with_capacity(population.len());
+        for &index in &ranked[..elites] {
+            genomes.push(population.genomes[index].clone());
+            next_fitness.push(Some(fitness[index]));
+        }
+        while genomes.len() < population.len() {
+            let parent = self.select(&fitness, &ranked);
+            let crossed = self.crossover.is_some() && self.rng.gen_bool(self.crossover_rate);
+            let other = crossed.then(|| self.select(&fitness, &ranked));
+            let mut child = match (&self.crossover, other) {
+                (Some(crossover), Some(other)) => crossover.cross(
+                    &population.genomes[parent],
+                    &population.genomes[other],
+                    &mut self.rng,
+                ),
+                _ => population.genomes[parent].clone(),
+            };
+            self.mutation.mutate(&mut child, &mut self.rng);
+            genomes.push(child);
+            next_fitness.push(None);
+        }
+        population.genomes = genomes;
+        population.fitness = next_fitness;
+        self.generation += 1;
+        self.evaluate(population)
+    }
+
+    /// Evaluate the population and run generations, returning the
+    /// statistics of the initial population and of every generation.
+    pub fn run(&mut self, population: &mut Population, generations: usize) -> Vec<GenerationStats> {
+        let mut stats = vec![self.evaluate(population)];
+        for _ in 0..generations {
+            stats.push(self.generation(population));
+        }
+        stats
+    }
+
+    // the index of a selected parent
+    fn select(&mut self, fitness: &[f64], ranked: &[usize]) -> usize {
+        match self.selection {
+            Selection::Tournament(size) => (0..size)
+                .map(|_| self.rng.gen_range(0..fitness.len()))
+                .reduce(|best, other| {
+                    if fitness[other] > fitness[best] {
+                        other
+                    } else {
+                        best
+                    }
+                })
+                .unwrap(),
+            Selection::Truncation(fraction) => {
+                let count = (fitness.len() as f64 * fraction).ceil() as usize;
+                ranked[self.rng.gen_range(0..count.clamp(1, fitness.len()))]
+            }
+            Selection::FitnessProportional => {
+                // the weights are relative to the least finite fitness, and
+                // individuals with a fitness that isn't finite have none
+                let worst = fitness
+                    .iter()
+                    .copied()
+                    .filter(|fitness| fitness.is_finite())
+                    .fold(f64::INFINITY, f64::min);
+                let weight = |fitness: f64| {
+                    if fitness.is_finite() {
+                        fitness - worst
+                    } else {
+                        0.0
+                    }
+                };
+                let total = fitness.iter().map(|&fitness| weight(fitness)).sum::<f64>();
+                if !(total.is_finite() && total > 0.0) {
+                    return self.rng.gen_range(0..fitness.len());
+                }
+                let mut chosen = self.rng.gen_range(0.0..total);
+                for (index, &fitness) in fitness.iter().enumerate() {
+                    chosen -= weight(fitness);
+                    if chosen < 0.0 {
+                        return index;
+                    }
+                }
+                // rounding left some of the total over
+                fitness
+                    .iter()
+                    .rposition(|&fitness| weight(fitness) > 0.0)
+                    .unwrap()
+            }
+        }
+    }
+}
+
+/// Which individual a child replaces in steady state evolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Replacement {
+    /// The least fit individual in the population.
+    #[default]
+    Worst,
+    /// The least fit individual in the tournament that selected the
+    /// first parent.
+    Loser,
+}
+
+/// Statistics about the population after a step of steady state
+/// evolution.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepStats {
+    /// The step, counting from 1.
+    pub step: usize,
+    /// The fitness of the child bred in this step.
+    pub child: f64,
+    /// The index of the individual the child replaced.
+    pub replaced: usize,
+    pub best: f64,
+    pub mean: f64,
+    pub worst: f64,
+}
+
+type MutateBytes = Box<dyn FnMut(&mut Vec<Vec<u8>>, &mut dyn RngCore)>;
+type CrossBytes = Box<dyn FnMut(&[Vec<u8>], &[Vec<u8>], &mut dyn RngCore) -> Vec<Vec<u8>>>;
+
+/// A mutation of genomes as a mutation of the bytes of strands, for
+/// [`SteadyState::mutation`].
+pub fn strand_mutation(mutation: impl Mutation + 'static) -> MutateBytes {
+    Box::new(move |strands, rng| {
+        let mut genome = Genome::new(std::mem::take(strands));
+        mutation.mutate(&mut genome, rng);
+        *strands = genome.strands().to_vec();
+    })
+}
+
+/// A crossover of genomes as a crossover of the bytes of strands, for
+/// [`SteadyState::crossover`].
+pub fn strand_crossover(crossover: impl Crossover + 'static) -> CrossBytes {
+    Box::new(move |first, second, rng| {
+        let first = Genome::new(first.to_vec());
+        let second = Genome::new(second.to_vec());
+        crossover.cross(&first, &second, rng).strands().to_vec()
+    })
+}
+
+/// Steady state evolution: each step selects parents by tournament,
+/// breeds a single child and puts it in the place of another individual,
+/// so the population never grows. Children are bred by closures over the
+/// bytes of the strands of their parents; [`strand_mutation`] and
+/// [`strand_crossover`] turn the operators of genomes into such closures.
+/// It is configured with consuming methods:
+///
+/// ```
+/// use evoby::{strand_crossover, Genome, OnePointCrossover, Population, Replacement, SteadyState};
+/// use rand::Rng;
+///
+/// let mut steady_state = SteadyState::new(|genome: &Genome| genome.len() as f64)
+///     .tournament(4)
+///     .replacement(Replacement::Loser)
+///     .crossover(strand_crossover(OnePointCrossover))
+///     .mutation(|strands, rng| strands[0].push(rng.gen()))
+///     .seed(42);
+/// let mut population = Population::new(vec![Genome::from_bytes(&[0x18]); 10]);
+/// let stats = steady_state.run(&mut population, 100);
+/// assert_eq!(stats.len(), 100);
+/// ```
+pub struct SteadyState<F> {
+    fitness: F,
+    tournament: usize,
+    replacement: Replacement,
+    mutation: Option<MutateBytes>,
+    crossover: Option<CrossBytes>,
+    rng: SmallRng,
+    step: usize,
+}
+
+impl<F: Fitness> SteadyState<F> {
+    pub fn new(fitness: F) -> SteadyState<F> {
+        SteadyState {
+            fitness,
+            tournament: 4,
+            replacement: Replacement::default(),
+            mutation: None,
+            crossover: None,
+            rng: SmallRng::seed_from_u64(0),
+            step: 0,
+        }
+    }
+
+    /// The amount of individuals in a tournament, of which the fittest is
+    /// a parent. By default this is 4. A smaller population has smaller
+    /// tournaments.
+    pub fn tournament(mut self, tournament: usize) -> SteadyState<F> {
+        assert!(tournament > 0);
+        self.tournament = tournament;
+        self
+    }
+
+    /// Which individual a child replaces. By default this is the worst.
+    pub fn replacement(mut self, replacement: Replacement) -> SteadyState<F> {
+        self.replacement = replacement;
+        self
+    }
+
+    /// How a child is mutated, by changing the bytes of its strands. By
+    /// default children aren't mutated.
+    pub fn mutation(
+        mut self,
+        mutation: impl FnMut(&mut Vec<Vec<u8>>, &mut dyn RngCore) + 'static,
+    ) -> SteadyState<F> {
+        self.mutation = Some(Box::new(mutation));
+        self
+    }
+
+    /// How the strands of two parents are crossed into the strands of a
+    /// child. The second parent is the winner of another tournament.
+    /// Without a crossover, a child is a copy of the first parent.
+    pub fn crossover(
+        mut self,
+        crossover: impl FnMut(&[Vec<u8>], &[Vec<u8>], &mut dyn RngCore) -> Vec<Vec<u8>> + 'static,
+    ) -> SteadyState<F> {
+        self.crossover = Some(Box::new(crossover));
+        self
+    }
+
+    /// The seed for all randomness of evolution.
+    pub fn seed(mut self, seed: u64) -> SteadyState<F> {
+        self.rng = SmallRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Breed a child, evaluate it and put it in the population. Genomes
+    /// that aren't evaluated yet are evaluated first. The population can't
+    /// be empty.
+    pub fn step(&mut self, population: &mut Population) -> StepStats {
+        assert!(!population.is_empty(), "the population is empty");
+        population.evaluate(&mut self.fitness);
+        let (parent, loser) = compete(&mut self.rng, self.tournament, population);
+        let mut strands = match &mut self.crossover {
+            Some(crossover) => {
+                let (other, _) = compete(&mut self.rng, self.tournament, population);
+                crossover(
+                    population.genomes[parent].strands(),
+                    population.genomes[other].strands(),
+                    &mut self.rng,
+                )
+            }
+            None => population.genomes[parent].strands().to_vec(),
+        };
+        if let Some(mutation) = &mut self.mutation {
+            mutation(&mut strands, &mut self.rng);
+        }
+        let child = Genome::new(strands);
+        let fitness = evaluate(&mut self.fitness, &child);
+
+        let replaced = match self.replacement {
+            Replacement::Worst => worst(population, 0..population.len()),
+            Replacement::Loser => loser,
+        };
+        population.genomes[replaced] = child;
+        population.fitness[replaced] = Some(fitness);
+        self.step += 1;
+        let (best, mean, worst) = population.summary();
+        StepStats {
+            step: self.step,
+            child: fitness,
+            replaced,
+            best,
+            mean,
+            worst,
+        }
+    }
+
+    /// Run steps, returning the statistics of every step.
+    pub fn run(&mut self, population: &mut Population, steps: usize) -> Vec<StepStats> {
+        (0..steps).map(|_| self.step(population)).collect()
+    }
+}
+
+// the fittest and the least fit of a tournament of distinct individuals
+fn compete(rng: &mut SmallRng, size: usize, population: &Population) -> (usize, usize) {
+    let entrants = sample(rng, population.len(), size.min(population.len()));
+    let fittest = entrants
+        .iter()
+        .reduce(|best, other| {
+            if fitness(population, other) > fitness(population, best) {
+                other
+            } else {
+                best
+            }
+        })
+        .unwrap();
+    (fittest, worst(population, entrants.into_iter()))
+}
+
+fn fitness(population: &Population, index: usize) -> f64 {
+    population.fitness[index].unwrap()
+}
+
+// the least fit of the individuals, the last of equally unfit ones
+fn worst(population: &Population, indexes: impl Iterator<Item = usize>) -> usize {
+    indexes
+        .reduce(|worst, other| {
+            if fitness(population, other) <= fitness(population, worst) {
+                other
+            } else {
+                worst
+            }
+        })
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use crate::crossover::OnePointCrossover;
+    use crate::mutation::PointMutation;
+
+    use super::*;
+
+    // the amount of inc r0 instructions
+    fn count_inc(genome: &Genome) -> f64 {
+        genome.strands()[0]
+            .iter()
+            .filter(|&&byte| byte == 0x18)
+            .count() as f64
+    }
+
+    fn population() -> Population {
+        Population::new(
+            (0..8)
+                .map(|i| Genome::from_bytes(&[0x18, 0x19, 0x1A, 0x1B][..i % 4 + 1]))
+                .collect(),
+        )
+    }
+
+    fn evaluated(fitness: &[f64]) -> Population {
+        let genomes = fitness
+            .iter()
+            .map(|&fitness| Genome::from_bytes(&vec![0x18; fitness as usize]))
+            .collect();
+        let mut population = Population::new(genomes);
+        Evolver::new(count_inc).evaluate(&mut population);
+        population
+    }
+
+    fn select_many(selection: Selection, fitness: &[f64]) -> Vec<usize> {
+        let mut evolver = Evolver::new(count_inc).selection(selection);
+        let mut ranked = (0..fitness.len()).collect::<Vec<_>>();
+        ranked.sort_by(|&a, &b| fitness[b].total_cmp(&fitness[a]));
+        (0..100).map(|_| evolver.select(fitness, &ranked)).collect()
+    }
+
+    #[test]
+    fn test_population() {
+        let mut population = Population::new(vec![Genome::from_bytes(&[0x18, 0x18])]);
+        assert_eq!(population.len(), 1);
+        assert_eq!(population.fitness(), &[None]);
+        assert_eq!(population.best(), None);
+        let stats = Evolver::new(count_inc).evaluate(&mut population);
+        assert_eq!(population.fitness(), &[Some(2.0)]);
+        assert_eq!(population.best(), Some((&population.genomes()[0], 2.0)));
+        assert_eq!(
+            stats,
+            GenerationStats {
+                generation: 0,
+                best: 2.0,
+                mean: 2.0,
+                worst: 2.0,
+                mean_length: 2.0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_nan_fitness() {
+        let mut population = population();
+        Evolver::new(|genome: &Genome| if genome.len() > 1 { f64::NAN } else { 1.0 })
+            .evaluate(&mut population);
+        assert_eq!(population.fitness()[0], Some(1.0));
+        assert_eq!(population.fitness()[1], Some(f64::NEG_INFINITY));
+        assert_eq!(population.best().unwrap().1, 1.0);
+        let stats = SteadyState::new(|_: &Genome| f64::NAN).step(&mut population);
+        assert_eq!(stats.child, f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_fitness_proportional_not_finite() {
+        // an individual that isn't finite doesn't turn off selection
+        for other in [f64::NAN, f64::NEG_INFINITY, f64::INFINITY] {
+            let fitness = [1.0, other, 1.0, 3.0];
+            let selected = select_many(Selection::FitnessProportional, &fitness);
+            assert!(!selected.contains(&1));
+            assert!(!selected.contains(&0) && !selected.contains(&2));
+            assert!(selected.contains(&3));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "the population is empty")]
+    fn test_evaluate_empty() {
+        Evolver::new(count_inc).run(&mut Population::new(Vec::new()), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "the population is empty")]
+    fn test_step_empty() {
+        SteadyState::new(count_inc).step(&mut Population::new(Vec::new()));
+    }
+
+    #[test]
+    fn test_best() {
+        let population = evaluated(&[1.0, 3.0, 3.0, 2.0]);
+        let (genome, fitness) = population.best().unwrap();
+        assert_eq!(fitness, 3.0);
+        assert!(std::ptr::eq(genome, &population.genomes()[1]));
+    }
+
+    #[test]
+    fn test_tournament() {
+        let fitness = [0.0, 1.0, 2.0, 3.0];
+        // a tournament of one selects at random
+        let mut any = select_many(Selection::Tournament(1), &fitness);
+        any.sort();
+        any.dedup();
+        assert_eq!(any, vec![0, 1, 2, 3]);
+        // a large tournament almost always selects the fittest
+        let selected = select_many(Selection::Tournament(20), &fitness);
+        assert!(selected.iter().filter(|&&index| index == 3).count() > 90);
+    }
+
+    #[test]
+    fn test_truncation() {
+        let selected = select_many(Selection::Truncation(0.5), &[0.0, 3.0, 1.0, 2.0]);
+        assert!(selected.iter().all(|&index| index == 1 || index == 3));
+        assert!(selected.contains(&1) && selected.contains(&3));
+    }
+
+    #[test]
+    fn test_fitness_proportional() {
+        let selected = select_many(Selection::FitnessProportional, &[-1.0, 2.0, -1.0, 0.0]);
+        // the least fit have no chance, and one is three times as fit
+        // as the other
+        assert!(selected.iter().all(|&index| index == 1 || index == 3));
+        let fittest = selected.iter().filter(|&&index| index == 1).count();
+        assert!(fittest > 60);
+        // when all are as fit, any is selected
+        let selected = select_many(Selection::FitnessProportional, &[1.0, 1.0]);
+        assert!(selected.contains(&0) && selected.contains(&1));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_tournament_of_none() {
+        Evolver::new(count_inc).selection(Selection::Tournament(0));
+    }
+
+    #[test]
+    fn test_elites() {
+        let mut population = evaluated(&[1.0, 3.0, 2.0]);
+        let mut evolver = Evolver::new(|_: &Genome| 0.0)
+            .elites(2)
+            .mutation(PointMutation::new(1.0));
+        let stats = evolver.generation(&mut population);
+        assert_eq!(stats.generation, 1);
+        // the elites are kept with their fitness, the child is evaluated
+        assert_eq!(population.fitness(), &[Some(3.0), Some(2.0), Some(0.0)]);
+        assert_eq!(population.genomes()[0], Genome::from_bytes(&[0x18; 3]));
+        assert_eq!(stats.best, 3.0);
+        assert_eq!(stats.worst, 0.0);
+    }
+
+    #[test]
+    fn test_crossover_rate() {
+        let mut population = evaluated(&[1.0, 2.0]);
+        let mut evolver = Evolver::new(count_inc)
+            .crossover(OnePointCrossover)
+            .crossover_rate(0.0);
+        evolver.generation(&mut population);
+        // without crossover or mutation, children are copies of parents
+        for genome in population.genomes() {
+            assert!(genome.len() == 1 || genome.len() == 2);
+        }
+    }
+
+    // the same seed gives the same result, and another seed another one
+    fn assert_reproducible<T: PartialEq + Debug>(run: impl Fn(u64) -> T) {
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+    }
+
+    #[test]
+    fn test_same_seed() {
+        assert_reproducible(|seed| {
+            let mut population = population();
+            let mut evolver = Evolver::new(count_inc)
+                .crossover(OnePointCrossover)
+                .mutation(PointMutation::new(0.2))
+                .seed(seed);
+            let stats = evolver.run(&mut population, 5);
+            (population, stats)
+        });
+        assert_reproducible(|seed| {
+            let mut population = population();
+            let mut steady_state = SteadyState::new(count_inc)
+                .crossover(strand_crossover(OnePointCrossover))
+                .mutation(strand_mutation(PointMutation::new(0.2)))
+                .seed(seed);
+            let stats = steady_state.run(&mut population, 20);
+            (population, stats)
+        });
+    }
+
+    #[test]
+    fn test_run() {
+        let mut population = population();
+        let mut evolver = Evolver::new(count_inc)
+            .selection(Selection::Tournament(3))
+            .crossover(OnePointCrossover)
+            .mutation(PointMutation::new(0.1))
+            .elites(1);
+        let stats = evolver.run(&mut population, 20);
+        assert_eq!(stats.len(), 21);
+        assert_eq!(stats[20].generation, 20);
+        // with an elite the best never gets worse
+        for window in stats.windows(2) {
+            assert!(window[1].best >= window[0].best);
+        }
+        assert!(stats[20].best > stats[0].best);
+        assert!(population.fitness().iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn test_step_replaces_worst() {
+        let mut population = evaluated(&[1.0, 3.0, 0.0, 2.0]);
+        let mut steady_state =
+            SteadyState::new(count_inc).mutation(|strands, _| strands[0].push(0x18));
+        let stats = steady_state.step(&mut population);
+        assert_eq!(stats.step, 1);
+        assert_eq!(stats.replaced, 2);
+        assert_eq!(stats.child, 4.0);
+        assert_eq!((stats.best, stats.mean, stats.worst), (4.0, 2.5, 1.0));
+        assert_eq!(population.fitness()[2], Some(4.0));
+        assert_eq!(population.len(), 4);
+    }
+
+    #[test]
+    fn test_step_replaces_loser() {
+        let mut population = evaluated(&[1.0, 3.0, 0.0, 2.0]);
+        // a tournament of the whole population
+        let mut steady_state = SteadyState::new(count_inc)
+            .tournament(10)
+            .replacement(Replacement::Loser);
+        let stats = steady_state.step(&mut population);
+        assert_eq!(stats.replaced, 2);
+        assert_eq!(stats.child, 3.0);
+        // a tournament of one replaces the parent with its copy
+        let mut steady_state = SteadyState::new(count_inc)
+            .tournament(1)
+            .replacement(Replacement::Loser);
+        let before = population.clone();
+        steady_state.step(&mut population);
+        assert_eq!(population, before);
+    }
+
+    #[test]
+    fn test_step_crossover() {
+        let mut population = evaluated(&[1.0, 2.0]);
+        let mut steady_state =
+            SteadyState::new(count_inc)
+                .tournament(2)
+                .crossover(|first, second, _| {
+                    vec![[first[0].as_slice(), second[0].as_slice()].concat()]
+                });
+        let stats = steady_state.step(&mut population);
+        assert_eq!(stats.child, 4.0);
+        assert_eq!(population.genomes()[0].len(), 4);
+    }
+
+    #[test]
+    fn test_step_evaluates() {
+        let mut population = population();
+        let stats = SteadyState::new(count_inc).step(&mut population);
+        assert!(population.fitness().iter().all(Option::is_some));
+        assert_eq!(stats.best, 1.0);
+    }
+
+    #[test]
+    fn test_steady_state_run() {
+        let mut population = population();
+        let mut steady_state = SteadyState::new(count_inc)
+            .crossover(|first, second, rng| {
+                // pick each byte from either parent
+                let bytes = first[0]
+                    .iter()
+                    .zip(&second[0])
+                    .map(|(&a, &b)| if rng.gen_bool(0.5) { a } else { b })
+                    .collect();
+                vec![bytes]
+            })
+            .mutation(|strands, rng| {
+                if rng.gen_bool(0.5) {
+                    strands[0].push(rng.gen_range(0x18..0x1C));
+                }
+            });
+        let stats = steady_state.run(&mut population, 200);
+        assert_eq!(stats.len(), 200);
+        assert_eq!(stats[199].step, 200);
+        // replacing the worst never makes the best worse
+        for window in stats.windows(2) {
+            assert!(window[1].best >= window[0].best);
+        }
+        assert!(stats[199].best > 2.0);
+        assert_eq!(population.len(), 8);
+    }
+}
@@ -5,65 +5,703 @@
 //
 // we can use a subset of the match, from a particular index
 // into the stored patterns.
+//
+// the distance between patterns is measured by a metric, which is
+// hamming distance by default. a match policy decides which of the
+// matching patterns we pick.
+//
+// patterns can be masked, so that only some of their bits count. only the
+// bits in the mask of both patterns are compared.
+
+use std::collections::HashMap;
 
 use rand::Rng;
 
-pub(crate) struct FuzzyBitMap<V> {
-    data: Vec<(u32, V)>,
+use crate::metric::{Hamming, Metric};
+
+// the fewest bits in a chunk of the index. narrower chunks have so few
+// keys that each finds a large share of the patterns, and looking through
+// all of them costs more than a scan.
+const MIN_CHUNK_BITS: u32 = 4;
+
+// the most chunks we split patterns into for the index
+const MAX_CHUNKS: usize = (32 / MIN_CHUNK_BITS) as usize;
+
+// the most matches a pass over the candidates keeps in order. when there
+// are more matches, the next ones are found by another pass.
+const WINDOW: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct FuzzyBitMap<V, M = Hamming> {
+    data: Vec<(Masked, V)>,
+    // the indexes in data of the patterns with a partial mask, in
+    // ascending order. masks hide bits from the index, so these are
+    // always looked at.
+    partial: Vec<usize>,
+    metric: M,
     max_distance: u32,
-    match_chance: f64,
+    policy: MatchPolicy,
+    // the index is a multi-index hash. the patterns are split into
+    // max_distance + 1 chunks of bits, so a pattern within max_distance
+    // must have at least one chunk that's equal. for each chunk we keep
+    // the indexes in data of the patterns with that chunk value. this
+    // only works for metrics that are at least the hamming distance;
+    // for other metrics there are no chunks, and neither are there when
+    // max_distance would make the chunks too narrow. only the patterns
+    // without a partial mask are in the index. without chunks, or for a
+    // pattern with a partial mask, we look at all patterns.
+    chunks: Vec<Chunk>,
+}
+
+/// A pattern with the bits that count when it's matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Masked {
+    pub pattern: u32,
+    pub mask: u32,
+}
+
+impl Masked {
+    pub fn new(pattern: u32, mask: u32) -> Masked {
+        Masked { pattern, mask }
+    }
+
+    fn is_partial(&self) -> bool {
+        self.mask != u32::MAX
+    }
+}
+
+// a pattern without a mask has all bits that count
+impl From<u32> for Masked {
+    fn from(pattern: u32) -> Masked {
+        Masked::new(pattern, u32::MAX)
+    }
+}
+
+/// How [`FuzzyBitMap::get`] picks one of the matching values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchPolicy {
+    /// Go through the matches from the closest to the furthest, and accept
+    /// each with this chance. We may not pick any.
+    Chance(f64),
+    /// Always pick the closest match. Of matches at the same distance the
+    /// first inserted is picked.
+    Best,
+    /// Go through the matches from the closest to the furthest, and accept
+    /// each with this chance to the power of its distance, so an exact
+    /// match is always accepted. We may not pick any.
+    Decay(f64),
+    /// Pick a match with a probability proportional to exp(-distance /
+    /// temperature). A temperature of 0 or less picks the closest match.
+    Softmax { temperature: f64 },
+    /// Pick a match with a probability proportional to one more than the
+    /// maximum distance minus its distance.
+    Roulette,
+}
+
+impl MatchPolicy {
+    // panic on a chance that isn't a probability, rather than when a
+    // lookup uses it
+    pub(crate) fn check(self) -> MatchPolicy {
+        match self {
+            MatchPolicy::Chance(chance) | MatchPolicy::Decay(chance) => assert!(
+                (0.0..=1.0).contains(&chance),
+                "match chance {} is not within 0 and 1",
+                chance
+            ),
+            MatchPolicy::Softmax { temperature } => {
+                assert!(!temperature.is_nan(), "softmax temperature is NaN")
+            }
+            MatchPolicy::Best | MatchPolicy::Roulette => {}
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Chunk {
+    shift: u32,
+    mask: u32,
+    indexes: HashMap<u32, Vec<usize>>,
+}
+
+impl Chunk {
+    fn key(&self, pattern: u32) -> u32 {
+        (pattern >> self.shift) & self.mask
+    }
+
+    // add the index of a pattern, keeping the indexes in ascending order
+    fn add(&mut self, pattern: u32, index: usize) {
+        let indexes = self.indexes.entry(self.key(pattern)).or_default();
+        let position = indexes.partition_point(|&i| i < index);
+        indexes.insert(position, index);
+    }
+
+    // take away the index of a pattern
+    fn take(&mut self, pattern: u32, index: usize) {
+        let key = self.key(pattern);
+        if let Some(indexes) = self.indexes.get_mut(&key) {
+            if let Ok(position) = indexes.binary_search(&index) {
+                indexes.remove(position);
+            }
+            if indexes.is_empty() {
+                self.indexes.remove(&key);
+            }
+        }
+    }
 }
 
 impl<V> FuzzyBitMap<V> {
-    pub(crate) fn new(max_distance: u32, match_chance: f64) -> FuzzyBitMap<V> {
+    pub fn new(max_distance: u32, match_chance: f64) -> FuzzyBitMap<V> {
+        FuzzyBitMap::with_metric(Hamming, max_distance, match_chance)
+    }
+}
+
+impl<V, M: Metric> FuzzyBitMap<V, M> {
+    pub fn with_metric(metric: M, max_distance: u32, match_chance: f64) -> FuzzyBitMap<V, M> {
+        let chunks = if metric.at_least_hamming() {
+            chunks(max_distance)
+        } else {
+            Vec::new()
+        };
         FuzzyBitMap {
             data: Vec::new(),
+            partial: Vec::new(),
+            metric,
             max_distance,
-            match_chance,
+            policy: MatchPolicy::Chance(match_chance).check(),
+            chunks,
         }
     }
 
-    pub(crate) fn insert(&mut self, pattern: u32, value: V) {
+    /// Pick matches with another policy. A chance of the policy outside 0
+    /// and 1 panics.
+    pub fn with_policy(mut self, policy: MatchPolicy) -> FuzzyBitMap<V, M> {
+        self.policy = policy.check();
+        self
+    }
+
+    pub fn metric(&self) -> &M {
+        &self.metric
+    }
+
+    pub fn insert(&mut self, pattern: impl Into<Masked>, value: V) {
+        let pattern = pattern.into();
+        self.add_index(pattern, self.data.len());
         self.data.push((pattern, value));
     }
 
-    pub(crate) fn matching(&self, pattern: u32, index: usize) -> Vec<&V> {
-        let mut matching_patterns = Vec::new();
-        for i in index..self.data.len() {
-            let (stored_pattern, value) = &self.data[i];
-            let distance = hamming_distance(pattern, *stored_pattern);
+    /// The amount of stored patterns.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The pattern and value at an index, in the order they were inserted.
+    pub fn entry(&self, index: usize) -> Option<(Masked, &V)> {
+        self.data
+            .get(index)
+            .map(|(pattern, value)| (*pattern, value))
+    }
+
+    /// All patterns and values, in the order they were inserted.
+    pub fn iter(&self) -> impl Iterator<Item = (Masked, &V)> {
+        self.data.iter().map(|(pattern, value)| (*pattern, value))
+    }
+
+    /// Insert a pattern at an index, shifting the patterns from there on
+    /// one index up. This takes time linear in the amount of patterns,
+    /// but doesn't rebuild the index.
+    pub fn insert_at(&mut self, index: usize, pattern: impl Into<Masked>, value: V) {
+        self.insert_many([(index, pattern.into(), value)]);
+    }
+
+    /// Insert patterns at indexes in ascending order, as
+    /// [`FuzzyBitMap::insert_at`] would one after the other. The index is
+    /// shifted once for all of them, so this takes time linear in the
+    /// amount of patterns however many are inserted.
+    pub fn insert_many(&mut self, entries: impl IntoIterator<Item = (usize, Masked, V)>) {
+        let mut data = Vec::with_capacity(self.data.len());
+        // the new index of each index before the insertions
+        let mut shifted = Vec::with_capacity(self.data.len());
+        let mut inserted = Vec::new();
+        let mut old = std::mem::take(&mut self.data).into_iter();
+        for (index, pattern, value) in entries {
+            assert!(index >= data.len(), "indexes to insert at aren't ascending");
+            while data.len() < index {
+                let entry = old.next().expect("index to insert at is out of bounds");
+                shifted.push(data.len());
+                data.push(entry);
+            }
+            inserted.push((pattern, index));
+            data.push((pattern, value));
+        }
+        for entry in old {
+            shifted.push(data.len());
+            data.push(entry);
+        }
+        self.data = data;
+        // the shift keeps the indexes in ascending order
+        let indexes = self
+            .chunks
+            .iter_mut()
+            .flat_map(|chunk| chunk.indexes.values_mut());
+        for indexes in indexes.chain([&mut self.partial]) {
+            for i in indexes.iter_mut() {
+                *i = shifted[*i];
+            }
+        }
+        for (pattern, index) in inserted {
+            self.add_index(pattern, index);
+        }
+    }
+
+    /// Remove the pattern at an index, shifting the patterns after it one
+    /// index down. Like [`FuzzyBitMap::insert_at`], this takes linear time.
+    pub fn remove(&mut self, index: usize) -> (Masked, V) {
+        assert!(index < self.data.len());
+        self.remove_many([index]).pop().unwrap()
+    }
+
+    /// Remove the patterns at ascending indexes, as
+    /// [`FuzzyBitMap::remove`] would one after the other from the last,
+    /// and return them in the same order. Like
+    /// [`FuzzyBitMap::insert_many`], the index is shifted once.
+    pub fn remove_many(&mut self, indexes: impl IntoIterator<Item = usize>) -> Vec<(Masked, V)> {
+        let mut removing = indexes.into_iter().peekable();
+        let mut data = Vec::with_capacity(self.data.len());
+        let mut removed = Vec::new();
+        // the new index of each index before the removals, or None for
+        // the ones removed
+        let mut shifted = Vec::with_capacity(self.data.len());
+        for (index, entry) in std::mem::take(&mut self.data).into_iter().enumerate() {
+            if removing.next_if_eq(&index).is_some() {
+                shifted.push(None);
+                removed.push(entry);
+            } else {
+                shifted.push(Some(data.len()));
+                data.push(entry);
+            }
+        }
+        assert!(
+            removing.next().is_none(),
+            "indexes to remove aren't ascending or are out of bounds"
+        );
+        self.data = data;
+        let shift = |indexes: &mut Vec<usize>| {
+            indexes.retain_mut(|i| match shifted[*i] {
+                Some(index) => {
+                    *i = index;
+                    true
+                }
+                None => false,
+            });
+        };
+        for chunk in &mut self.chunks {
+            chunk.indexes.retain(|_, indexes| {
+                shift(indexes);
+                !indexes.is_empty()
+            });
+        }
+        shift(&mut self.partial);
+        removed
+    }
+
+    /// Change the pattern at an index, keeping its value and place.
+    pub fn set_pattern(&mut self, index: usize, pattern: impl Into<Masked>) {
+        let pattern = pattern.into();
+        let previous = std::mem::replace(&mut self.data[index].0, pattern);
+        if previous.is_partial() || pattern.is_partial() {
+            self.take_index(previous, index);
+            self.add_index(pattern, index);
+            return;
+        }
+        for chunk in &mut self.chunks {
+            if chunk.key(previous.pattern) != chunk.key(pattern.pattern) {
+                chunk.take(previous.pattern, index);
+                chunk.add(pattern.pattern, index);
+            }
+        }
+    }
+
+    // add the index of a pattern to the chunks, or to the partial patterns
+    fn add_index(&mut self, pattern: Masked, index: usize) {
+        if pattern.is_partial() {
+            let position = self.partial.partition_point(|&i| i < index);
+            self.partial.insert(position, index);
+            return;
+        }
+        for chunk in &mut self.chunks {
+            chunk.add(pattern.pattern, index);
+        }
+    }
+
+    // take away the index of a pattern, from wherever add_index put it
+    fn take_index(&mut self, pattern: Masked, index: usize) {
+        if pattern.is_partial() {
+            if let Ok(position) = self.partial.binary_search(&index) {
+                self.partial.remove(position);
+            }
+            return;
+        }
+        for chunk in &mut self.chunks {
+            chunk.take(pattern.pattern, index);
+        }
+    }
+
+    /// The distance from a pattern to a stored pattern, according to the
+    /// metric, comparing only the bits in both masks. When the masks have
+    /// no bits in common there is nothing to compare, and the distance is
+    /// the largest there is, so such patterns don't match.
+    pub fn distance(&self, pattern: impl Into<Masked>, stored: impl Into<Masked>) -> u32 {
+        let (pattern, stored) = (pattern.into(), stored.into());
+        let mask = pattern.mask & stored.mask;
+        if mask == 0 {
+            return u32::MAX;
+        }
+        self.metric
+            .distance(pattern.pattern & mask, stored.pattern & mask)
+    }
+
+    pub fn matching(&self, pattern: impl Into<Masked>, index: usize) -> Vec<&V> {
+        self.iter_matching(pattern, index).collect()
+    }
+
+    /// The values with a pattern within the maximum distance of pattern,
+    /// starting at index, from the closest to the furthest. Values at the
+    /// same distance are in the order they were inserted. This doesn't
+    /// allocate, and if the metric allows it only considers the patterns
+    /// the index finds. Each pass over them measures every candidate once
+    /// and keeps the closest matches in order, so unless there are many
+    /// matches a single pass finds them all.
+    pub fn iter_matching(&self, pattern: impl Into<Masked>, index: usize) -> Matching<'_, V, M> {
+        let pattern = pattern.into();
+        Matching {
+            fuzzy_bit_map: self,
+            pattern,
+            candidates: self.candidates(pattern, index),
+            window: [(0, 0); WINDOW],
+            start: 0,
+            end: 0,
+            last: None,
+            more: true,
+        }
+    }
+
+    /// Pick one of the matching values, according to the match policy.
+    pub fn get(&self, pattern: impl Into<Masked>, index: usize, rng: &mut impl Rng) -> Option<&V> {
+        self.pick(pattern.into(), index, rng)
+            .picked
+            .map(|(_, index, _)| &self.data[index].1)
+    }
+
+    /// Like [`FuzzyBitMap::get`], but also tell how the value was found.
+    pub fn lookup(
+        &self,
+        pattern: impl Into<Masked>,
+        index: usize,
+        rng: &mut impl Rng,
+    ) -> Lookup<'_, V> {
+        let pick = self.pick(pattern.into(), index, rng);
+        let found = pick.picked.map(|(distance, picked, rank)| Found {
+            value: &self.data[picked].1,
+            distance,
+            rank,
+        });
+        Lookup {
+            found,
+            considered: pick.considered,
+        }
+    }
+
+    /// Look up the closest matching value, whatever the policy.
+    pub fn lookup_best(&self, pattern: impl Into<Masked>, index: usize) -> Lookup<'_, V> {
+        let picked = self.iter_matching(pattern, index).next_match();
+        Lookup {
+            found: picked.map(|(distance, index)| Found {
+                value: &self.data[index].1,
+                distance,
+                rank: 0,
+            }),
+            considered: picked.is_some() as usize,
+        }
+    }
+
+    fn pick(&self, pattern: Masked, index: usize, rng: &mut impl Rng) -> Pick {
+        let mut matching = self.iter_matching(pattern, index);
+        let mut considered = 0;
+        let mut ordered = |accept: &mut dyn FnMut(u32) -> bool| {
+            let picked = std::iter::from_fn(|| matching.next_match())
+                .inspect(|_| considered += 1)
+                .find(|&(distance, _)| accept(distance))
+                .map(|(distance, index)| (distance, index, considered - 1));
+            Pick { picked, considered }
+        };
+        match self.policy {
+            // go through the list of matching patterns. prefer the ones earlier in the
+            // list to later ones. In other words, there's a slight chance we don't match.
+            MatchPolicy::Chance(chance) => ordered(&mut |_| rng.gen_bool(chance)),
+            MatchPolicy::Decay(decay) => {
+                ordered(&mut |distance| rng.gen_bool(decay.powf(distance as f64)))
+            }
+            MatchPolicy::Best => ordered(&mut |_| true),
+            MatchPolicy::Softmax { temperature } if temperature <= 0.0 => ordered(&mut |_| true),
+            MatchPolicy::Softmax { temperature } => {
+                // the weights are relative to the closest match, which
+                // comes first, so they don't all underflow when distances
+                // are large
+                let mut closest = None;
+                pick_weighted(&mut matching, rng, |distance| {
+                    let closest = *closest.get_or_insert(distance);
+                    (-((distance - closest) as f64) / temperature).exp()
+                })
+            }
+            MatchPolicy::Roulette => pick_weighted(&mut matching, rng, |distance| {
+                (self.max_distance - distance) as f64 + 1.0
+            }),
+        }
+    }
+
+    fn candidates(&self, pattern: Masked, index: usize) -> Candidates<'_> {
+        let mut chunks: [&[usize]; MAX_CHUNKS] = [&[]; MAX_CHUNKS];
+        // the patterns with a partial mask aren't in the index
+        let partial = &self.partial[self.partial.partition_point(|&i| i < index)..];
+        // a mask hides bits from the index
+        let scan = self.chunks.is_empty() || pattern.is_partial();
+        if scan {
+            return Candidates {
+                chunks,
+                partial,
+                scan,
+                start: index,
+            };
+        }
+        for (candidates, chunk) in chunks.iter_mut().zip(&self.chunks) {
+            if let Some(indexes) = chunk.indexes.get(&chunk.key(pattern.pattern)) {
+                // the indexes are in ascending order
+                let start = indexes.partition_point(|&i| i < index);
+                *candidates = &indexes[start..];
+            }
+        }
+        Candidates {
+            chunks,
+            partial,
+            scan,
+            start: index,
+        }
+    }
+
+    // call f with the distance and index of each candidate within the
+    // maximum distance, in no particular order
+    fn for_each_match(
+        &self,
+        candidates: &Candidates,
+        pattern: Masked,
+        mut f: impl FnMut(u32, usize),
+    ) {
+        let mut consider = |index: usize| {
+            let distance = self.distance(pattern, self.data[index].0);
             if distance <= self.max_distance {
-                matching_patterns.push((distance, value));
+                f(distance, index);
             }
+        };
+        if candidates.scan {
+            for index in candidates.start..self.data.len() {
+                consider(index);
+            }
+            return;
+        }
+        for (i, indexes) in candidates.chunks.iter().enumerate() {
+            for &index in indexes.iter() {
+                // skip the candidates an earlier chunk found
+                let stored = self.data[index].0.pattern;
+                if !self.chunks[..i]
+                    .iter()
+                    .any(|chunk| chunk.key(stored) == chunk.key(pattern.pattern))
+                {
+                    consider(index);
+                }
+            }
+        }
+        for &index in candidates.partial {
+            consider(index);
         }
-        // sort by distance, making lower distances sort earlier
-        matching_patterns.sort_by(|a, b| a.0.cmp(&b.0));
-        matching_patterns
-            .into_iter()
-            .map(|(_, value)| value)
-            .collect()
     }
+}
 
-    pub(crate) fn get(&self, pattern: u32, index: usize, rng: &mut impl Rng) -> Option<&V> {
-        // go through the list of matching patterns. prefer the ones earlier in the
-        // list to later ones. In other words, there's a slight chance we don't match.
-        for value in self.matching(pattern, index) {
-            if rng.gen_bool(self.match_chance) {
-                return Some(value);
+/// The result of [`FuzzyBitMap::lookup`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct Lookup<'a, V> {
+    pub found: Option<Found<'a, V>>,
+    /// The amount of matches the policy considered.
+    pub considered: usize,
+}
+
+/// A value found by a lookup.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Found<'a, V> {
+    pub value: &'a V,
+    /// The distance to its pattern, according to the metric.
+    pub distance: u32,
+    /// How many matches are closer, or as close but inserted earlier.
+    pub rank: usize,
+}
+
+// the distance, index and rank of the match a policy picked, and the
+// amount of matches it considered
+struct Pick {
+    picked: Option<(u32, usize, usize)>,
+    considered: usize,
+}
+
+// pick a match with a probability proportional to its weight, going
+// through the matches in order so we know the rank of the one we pick
+fn pick_weighted<V, M: Metric>(
+    matching: &mut Matching<V, M>,
+    rng: &mut impl Rng,
+    mut weigh: impl FnMut(u32) -> f64,
+) -> Pick {
+    let mut total = 0.0;
+    let mut picked = None;
+    let mut considered = 0;
+    while let Some((distance, index)) = matching.next_match() {
+        let weight = weigh(distance);
+        total += weight;
+        // replace the match we picked so far with a chance of its share
+        // of the total weight
+        if rng.gen::<f64>() * total < weight {
+            picked = Some((distance, index, considered));
+        }
+        considered += 1;
+    }
+    Pick { picked, considered }
+}
+
+// the candidates for a match: the indexes found by each chunk and the
+// patterns with a partial mask, or when we scan all values from start
+struct Candidates<'a> {
+    chunks: [&'a [usize]; MAX_CHUNKS],
+    partial: &'a [usize],
+    scan: bool,
+    start: usize,
+}
+
+// split 32 bits into as many chunks as we need to find patterns within
+// max_distance. when that takes chunks narrower than MIN_CHUNK_BITS there
+// are no chunks, and we scan instead.
+fn chunks(max_distance: u32) -> Vec<Chunk> {
+    if max_distance as usize >= MAX_CHUNKS {
+        return Vec::new();
+    }
+    let count = max_distance + 1;
+    let mut shift = 0;
+    (0..count)
+        .map(|i| {
+            let bits = 32 / count + u32::from(i < 32 % count);
+            let chunk = Chunk {
+                shift,
+                mask: ((1u64 << bits) - 1) as u32,
+                indexes: HashMap::new(),
+            };
+            shift += bits;
+            chunk
+        })
+        .collect()
+}
+
+/// An iterator over matching values, see [`FuzzyBitMap::iter_matching`].
+pub struct Matching<'a, V, M = Hamming> {
+    fuzzy_bit_map: &'a FuzzyBitMap<V, M>,
+    pattern: Masked,
+    candidates: Candidates<'a>,
+    // the distance and index of the closest matches after the ones we
+    // returned before the last pass, ordered by distance and then by
+    // index. we didn't return those from start to end yet.
+    window: [(u32, usize); WINDOW],
+    start: usize,
+    end: usize,
+    // the distance and index of the last match we returned
+    last: Option<(u32, usize)>,
+    // whether another pass can find matches that didn't fit the window
+    more: bool,
+}
+
+impl<'a, V, M: Metric> Matching<'a, V, M> {
+    // the distance and index of the next match
+    fn next_match(&mut self) -> Option<(u32, usize)> {
+        if self.start == self.end {
+            if !self.more {
+                return None;
             }
+            self.pass();
         }
-        None
+        let next = self.window[self.start..self.end].first().copied()?;
+        self.start += 1;
+        self.last = Some(next);
+        Some(next)
+    }
+
+    // go through the candidates, and keep the closest matches after the
+    // last one we returned
+    fn pass(&mut self) {
+        let Matching {
+            fuzzy_bit_map,
+            pattern,
+            candidates,
+            window,
+            last,
+            more,
+            ..
+        } = self;
+        let mut end = 0;
+        *more = false;
+        fuzzy_bit_map.for_each_match(candidates, *pattern, |distance, index| {
+            let found = (distance, index);
+            if Some(found) <= *last {
+                return;
+            }
+            if end == WINDOW {
+                // the furthest match doesn't fit, whichever it is
+                *more = true;
+                if found > window[WINDOW - 1] {
+                    return;
+                }
+                end -= 1;
+            }
+            let position = window[..end].partition_point(|&other| other < found);
+            window.copy_within(position..end, position + 1);
+            window[position] = found;
+            end += 1;
+        });
+        self.start = 0;
+        self.end = end;
+    }
+}
+
+impl<'a, V, M: Metric> Iterator for Matching<'a, V, M> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<&'a V> {
+        let data = &self.fuzzy_bit_map.data;
+        self.next_match().map(|(_, index)| &data[index].1)
     }
 }
 
-fn hamming_distance(a: u32, b: u32) -> u32 {
+pub fn hamming_distance(a: u32, b: u32) -> u32 {
     (a ^ b).count_ones()
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
+    use proptest::prelude::*;
     use rand::{rngs::SmallRng, SeedableRng};
 
+    use crate::metric::{AbsoluteDifference, Streak, WeightedBits, Wrap};
+
     use super::*;
 
     #[test]
@@ -143,6 +781,288 @@ mod tests {
         assert_eq!(matches, None);
     }
 
+    #[test]
+    fn test_iter_matching_is_lazy() {
+        let mut fuzzy_bitmap = FuzzyBitMap::new(1, 0.5);
+        fuzzy_bitmap.insert(0b0001, 1);
+        fuzzy_bitmap.insert(0b0000, 0);
+        let mut matches = fuzzy_bitmap.iter_matching(0b0000, 0);
+        assert_eq!(matches.next(), Some(&0));
+        assert_eq!(matches.next(), Some(&1));
+        assert_eq!(matches.next(), None);
+    }
+
+    // hamming distance, counting how often it's measured
+    #[derive(Debug, Default)]
+    struct Counting(std::sync::atomic::AtomicUsize);
+
+    impl Metric for Counting {
+        fn distance(&self, pattern: u32, stored: u32) -> u32 {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            hamming_distance(pattern, stored)
+        }
+    }
+
+    // a counting metric the index can be used for
+    #[derive(Debug, Default)]
+    struct WithIndex(Counting);
+
+    impl Metric for WithIndex {
+        fn distance(&self, pattern: u32, stored: u32) -> u32 {
+            self.0.distance(pattern, stored)
+        }
+
+        fn at_least_hamming(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_iter_matching_single_pass() {
+        let mut fuzzy_bitmap = FuzzyBitMap::with_metric(Counting::default(), 32, 0.5);
+        for pattern in 0..WINDOW as u32 {
+            fuzzy_bitmap.insert(pattern, pattern);
+        }
+        assert_eq!(fuzzy_bitmap.iter_matching(0, 0).count(), WINDOW);
+        // each pattern is measured once, as all matches fit the window
+        assert_eq!(
+            fuzzy_bitmap
+                .metric()
+                .0
+                .swap(0, std::sync::atomic::Ordering::Relaxed),
+            WINDOW
+        );
+        // with more matches, each pass measures every pattern once more
+        for pattern in WINDOW as u32..2 * WINDOW as u32 {
+            fuzzy_bitmap.insert(pattern, pattern);
+        }
+        let matching = fuzzy_bitmap
+            .iter_matching(0, 0)
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(matching.len(), 2 * WINDOW);
+        assert!(matching
+            .windows(2)
+            .all(|pair| pair[0].count_ones() <= pair[1].count_ones()));
+        assert_eq!(
+            fuzzy_bitmap
+                .metric()
+                .0
+                .load(std::sync::atomic::Ordering::Relaxed),
+            2 * 2 * WINDOW
+        );
+    }
+
+    // the system allocator, counting the allocations of each thread
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            ALLOCATIONS.with(|allocations| allocations.set(allocations.get() + 1));
+            std::alloc::System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            std::alloc::System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(|allocations| allocations.get())
+    }
+
+    #[test]
+    fn test_lookup_doesnt_allocate() {
+        let policies = [
+            MatchPolicy::Chance(0.5),
+            MatchPolicy::Best,
+            MatchPolicy::Decay(0.5),
+            MatchPolicy::Softmax { temperature: 1.0 },
+            MatchPolicy::Roulette,
+        ];
+        // indexed, and scanned because of the maximum distance
+        for max_distance in [2, 16] {
+            for policy in policies {
+                let patterns = (0..4 * WINDOW as u32).collect::<Vec<_>>();
+                let fuzzy_bitmap = fuzzy_bitmap_with_policy(&patterns, max_distance, policy);
+                let expected = scan(&Hamming, &patterns, max_distance, 1, 0).len();
+                let mut rng = SmallRng::from_seed([0; 32]);
+                let before = allocations();
+                let found = fuzzy_bitmap.lookup(0, 0, &mut rng).found.is_some();
+                let count = fuzzy_bitmap.iter_matching(1, 0).count();
+                assert_eq!(allocations(), before);
+                assert!(found);
+                assert_eq!(count, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_partial_patterns_keep_the_index() {
+        let mut fuzzy_bitmap = FuzzyBitMap::with_metric(WithIndex::default(), 0, 0.5);
+        for pattern in 0..100 {
+            fuzzy_bitmap.insert(pattern, pattern);
+        }
+        fuzzy_bitmap.insert(Masked::new(0x0000_0007, 0x0000_FFFF), 100);
+        assert_eq!(fuzzy_bitmap.matching(7, 0), vec![&7, &100]);
+        // the index finds the full pattern, and the partial one is looked at
+        assert_eq!(
+            fuzzy_bitmap
+                .metric()
+                .0
+                 .0
+                .load(std::sync::atomic::Ordering::Relaxed),
+            2
+        );
+    }
+
+    #[test]
+    fn test_matching_with_metric() {
+        let mut fuzzy_bitmap = FuzzyBitMap::with_metric(Wrap, 10, 0.5);
+        fuzzy_bitmap.insert(100, 0);
+        fuzzy_bitmap.insert(95, 1);
+        fuzzy_bitmap.insert(103, 2);
+        fuzzy_bitmap.insert(111, 3);
+        assert_eq!(fuzzy_bitmap.matching(100, 0), vec![&0, &2]);
+        assert_eq!(fuzzy_bitmap.matching(100, 1), vec![&2]);
+    }
+
+    #[test]
+    fn test_matching_max_distance_32() {
+        let mut fuzzy_bitmap = FuzzyBitMap::new(32, 0.5);
+        fuzzy_bitmap.insert(u32::MAX, 0);
+        fuzzy_bitmap.insert(0b0001, 1);
+        assert_eq!(fuzzy_bitmap.matching(0, 0), vec![&1, &0]);
+    }
+
+    #[test]
+    fn test_chunks() {
+        assert_eq!(chunks(0).len(), 1);
+        let widths = chunks(7)
+            .iter()
+            .map(|chunk| chunk.mask.count_ones())
+            .collect::<Vec<_>>();
+        assert_eq!(widths, vec![MIN_CHUNK_BITS; MAX_CHUNKS]);
+        // narrower chunks aren't worth it
+        assert!(chunks(8).is_empty());
+        assert!(chunks(32).is_empty());
+    }
+
+    // the matches as a linear scan finds them
+    fn scan(
+        metric: &impl Metric,
+        data: &[u32],
+        max_distance: u32,
+        pattern: u32,
+        index: usize,
+    ) -> Vec<usize> {
+        let mut matching = (index..data.len())
+            .filter(|&i| metric.distance(pattern, data[i]) <= max_distance)
+            .collect::<Vec<_>>();
+        matching.sort_by_key(|&i| metric.distance(pattern, data[i]));
+        matching
+    }
+
+    proptest! {
+        #[test]
+        fn test_matching_like_scan(
+            // few bits differ, so that there are plenty of matches
+            data in prop::collection::vec(any::<u32>().prop_map(|p| p & 0x8421_ff00), 0..64),
+            pattern in any::<u32>().prop_map(|p| p & 0x8421_ff00),
+            max_distance in 0..=33u32,
+            index in 0..70usize,
+        ) {
+            let mut fuzzy_bitmap = FuzzyBitMap::new(max_distance, 0.5);
+            for (i, &stored_pattern) in data.iter().enumerate() {
+                fuzzy_bitmap.insert(stored_pattern, i);
+            }
+            let matching = fuzzy_bitmap
+                .iter_matching(pattern, index)
+                .copied()
+                .collect::<Vec<_>>();
+            prop_assert_eq!(matching, scan(&Hamming, &data, max_distance, pattern, index));
+        }
+
+        #[test]
+        fn test_matching_with_metric_like_scan(
+            data in prop::collection::vec(any::<u32>().prop_map(|p| p & 0x8421_ff00), 0..64),
+            pattern in any::<u32>().prop_map(|p| p & 0x8421_ff00),
+            max_distance in prop_oneof![0..=40u32, any::<u32>()],
+            index in 0..70usize,
+            metric in prop_oneof![
+                Just(Arc::new(Hamming) as Arc<dyn Metric>),
+                Just(Arc::new(Streak) as Arc<dyn Metric>),
+                Just(Arc::new(AbsoluteDifference) as Arc<dyn Metric>),
+                Just(Arc::new(Wrap) as Arc<dyn Metric>),
+                Just(Arc::new(WeightedBits::default()) as Arc<dyn Metric>),
+            ],
+        ) {
+            let mut fuzzy_bitmap = FuzzyBitMap::with_metric(metric.clone(), max_distance, 0.5);
+            for (i, &stored_pattern) in data.iter().enumerate() {
+                fuzzy_bitmap.insert(stored_pattern, i);
+            }
+            let matching = fuzzy_bitmap
+                .iter_matching(pattern, index)
+                .copied()
+                .collect::<Vec<_>>();
+            prop_assert_eq!(matching, scan(&metric, &data, max_distance, pattern, index));
+        }
+
+        #[test]
+        fn test_matching_masked_like_scan(
+            data in prop::collection::vec(
+                (any::<u32>(), prop_oneof![Just(u32::MAX), Just(0xFFFF_0000), Just(0x0000_00FF), any::<u32>()]),
+                0..64,
+            ),
+            pattern in any::<u32>(),
+            mask in prop_oneof![Just(u32::MAX), Just(0xFF00_0000), any::<u32>()],
+            max_distance in 0..=33u32,
+            index in 0..70usize,
+        ) {
+            let mut fuzzy_bitmap = FuzzyBitMap::new(max_distance, 0.5);
+            for (i, &(stored_pattern, stored_mask)) in data.iter().enumerate() {
+                fuzzy_bitmap.insert(Masked::new(stored_pattern, stored_mask), i);
+            }
+            let pattern = Masked::new(pattern, mask);
+            let matching = fuzzy_bitmap
+                .iter_matching(pattern, index)
+                .copied()
+                .collect::<Vec<_>>();
+            let mut expected = (index..data.len())
+                .map(|i| (fuzzy_bitmap.distance(pattern, Masked::new(data[i].0, data[i].1)), i))
+                .filter(|&(distance, _)| distance <= max_distance)
+                .collect::<Vec<_>>();
+            expected.sort();
+            prop_assert_eq!(matching, expected.into_iter().map(|(_, i)| i).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_matching_masked() {
+        let mut fuzzy_bitmap = FuzzyBitMap::new(0, 1.0);
+        fuzzy_bitmap.insert(Masked::new(0x1200_0000, 0xFF00_0000), 0);
+        fuzzy_bitmap.insert(0x1234_5678, 1);
+        // only the bits in both masks count
+        assert_eq!(fuzzy_bitmap.matching(0x12FF_FFFF, 0), vec![&0]);
+        // masks without bits in common don't match
+        assert_eq!(
+            fuzzy_bitmap.matching(Masked::new(0x0034_0000, 0x00FF_0000), 0),
+            vec![&1]
+        );
+        assert!(fuzzy_bitmap.matching(Masked::new(0, 0), 0).is_empty());
+        assert_eq!(
+            fuzzy_bitmap.distance(Masked::new(0x0F00_0000, 0xFF00_0000), 0),
+            4
+        );
+    }
+
     #[test]
     fn test_get_match_with_distance_1() {
         let mut fuzzy_bitmap = FuzzyBitMap::new(1, 0.5);
@@ -164,4 +1084,340 @@ mod tests {
         let matches = fuzzy_bitmap.get(0b0001, 0, &mut rng);
         assert_eq!(matches, Some(&0b0001));
     }
+
+    // how often each value is picked, with none picked last
+    fn pick_frequencies<M: Metric>(
+        fuzzy_bitmap: &FuzzyBitMap<usize, M>,
+        pattern: u32,
+        count: usize,
+    ) -> Vec<f64> {
+        let mut rng = SmallRng::from_seed([0; 32]);
+        let mut picked = vec![0; count + 1];
+        let samples = 10_000;
+        for _ in 0..samples {
+            let value = fuzzy_bitmap.get(pattern, 0, &mut rng).copied();
+            picked[value.unwrap_or(count)] += 1;
+        }
+        picked
+            .into_iter()
+            .map(|picked| picked as f64 / samples as f64)
+            .collect()
+    }
+
+    fn assert_frequencies(frequencies: &[f64], expected: &[f64]) {
+        assert_eq!(frequencies.len(), expected.len());
+        for (frequency, expected) in frequencies.iter().zip(expected) {
+            assert!(
+                (frequency - expected).abs() < 0.02,
+                "{:?} is not like {:?}",
+                frequencies,
+                expected
+            );
+        }
+    }
+
+    fn fuzzy_bitmap_with_policy(
+        patterns: &[u32],
+        max_distance: u32,
+        policy: MatchPolicy,
+    ) -> FuzzyBitMap<usize> {
+        let mut fuzzy_bitmap = FuzzyBitMap::new(max_distance, 1.0).with_policy(policy);
+        for (i, &pattern) in patterns.iter().enumerate() {
+            fuzzy_bitmap.insert(pattern, i);
+        }
+        fuzzy_bitmap
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_match_chance_out_of_range() {
+        FuzzyBitMap::<u32>::new(0, 1.5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_policy_out_of_range() {
+        FuzzyBitMap::<u32>::new(0, 0.5).with_policy(MatchPolicy::Decay(-0.1));
+    }
+
+    #[test]
+    fn test_policy_best() {
+        let fuzzy_bitmap =
+            fuzzy_bitmap_with_policy(&[0b11, 0b01, 0b10, 0b00], 2, MatchPolicy::Best);
+        assert_frequencies(
+            &pick_frequencies(&fuzzy_bitmap, 0, 4),
+            &[0.0, 0.0, 0.0, 1.0, 0.0],
+        );
+        // ties go to the first inserted
+        assert_frequencies(
+            &pick_frequencies(&fuzzy_bitmap, 0b100, 4),
+            &[0.0, 0.0, 0.0, 1.0, 0.0],
+        );
+        assert_frequencies(
+            &pick_frequencies(&fuzzy_bitmap, 0b111, 4),
+            &[1.0, 0.0, 0.0, 0.0, 0.0],
+        );
+        let fuzzy_bitmap = fuzzy_bitmap_with_policy(&[0b01, 0b10], 2, MatchPolicy::Best);
+        assert_frequencies(&pick_frequencies(&fuzzy_bitmap, 0, 2), &[1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_policy_chance() {
+        // the chance doesn't depend on how far the matches are
+        let fuzzy_bitmap =
+            fuzzy_bitmap_with_policy(&[0b00, 0b01, 0b11], 2, MatchPolicy::Chance(0.5));
+        assert_frequencies(
+            &pick_frequencies(&fuzzy_bitmap, 0, 3),
+            &[0.5, 0.25, 0.125, 0.125],
+        );
+    }
+
+    #[test]
+    fn test_policy_decay() {
+        let fuzzy_bitmap =
+            fuzzy_bitmap_with_policy(&[0b111, 0b001, 0b011], 3, MatchPolicy::Decay(0.5));
+        // the chances are 0.5, 0.25 and 0.125
+        assert_frequencies(
+            &pick_frequencies(&fuzzy_bitmap, 0, 3),
+            &[0.5 * 0.75 * 0.125, 0.5, 0.5 * 0.25, 0.5 * 0.75 * 0.875],
+        );
+        // an exact match is always accepted
+        assert_frequencies(
+            &pick_frequencies(&fuzzy_bitmap, 0b001, 3),
+            &[0.0, 1.0, 0.0, 0.0],
+        );
+    }
+
+    #[test]
+    fn test_policy_softmax() {
+        let policy = MatchPolicy::Softmax { temperature: 1.0 };
+        let total = 1.0 + (-1.0f64).exp() + (-2.0f64).exp();
+        let expected = [
+            1.0 / total,
+            (-1.0f64).exp() / total,
+            (-2.0f64).exp() / total,
+            0.0,
+        ];
+        let fuzzy_bitmap = fuzzy_bitmap_with_policy(&[0b00, 0b01, 0b11], 2, policy);
+        assert_frequencies(&pick_frequencies(&fuzzy_bitmap, 0, 3), &expected);
+        // large distances don't underflow
+        let mut fuzzy_bitmap = FuzzyBitMap::with_metric(Wrap, u32::MAX, 1.0).with_policy(policy);
+        fuzzy_bitmap.insert(1_000_000_000, 0);
+        fuzzy_bitmap.insert(1_000_000_001, 1);
+        fuzzy_bitmap.insert(1_000_000_002, 2);
+        assert_frequencies(&pick_frequencies(&fuzzy_bitmap, 0, 3), &expected);
+        // a cold softmax picks the best
+        let fuzzy_bitmap =
+            fuzzy_bitmap_with_policy(&[0b01, 0b00], 2, MatchPolicy::Softmax { temperature: 0.0 });
+        assert_frequencies(&pick_frequencies(&fuzzy_bitmap, 0, 2), &[0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_policy_roulette() {
+        let fuzzy_bitmap =
+            fuzzy_bitmap_with_policy(&[0b11, 0b00, 0b01, 0b111], 2, MatchPolicy::Roulette);
+        assert_frequencies(
+            &pick_frequencies(&fuzzy_bitmap, 0, 4),
+            &[1.0 / 6.0, 0.5, 1.0 / 3.0, 0.0, 0.0],
+        );
+        // there are no duplicates from the index
+        let fuzzy_bitmap = fuzzy_bitmap_with_policy(&[0, 0], 4, MatchPolicy::Roulette);
+        assert_frequencies(&pick_frequencies(&fuzzy_bitmap, 0, 2), &[0.5, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let fuzzy_bitmap = fuzzy_bitmap_with_policy(&[0b11, 0b01, 0b00], 2, MatchPolicy::Best);
+        let mut rng = SmallRng::from_seed([0; 32]);
+        let lookup = fuzzy_bitmap.lookup(0b10, 0, &mut rng);
+        assert_eq!(
+            lookup.found,
+            Some(Found {
+                value: &0,
+                distance: 1,
+                rank: 0
+            })
+        );
+        assert_eq!(lookup.considered, 1);
+        let lookup = fuzzy_bitmap.lookup(0b10, 1, &mut rng);
+        assert_eq!(lookup.found.map(|found| found.distance), Some(1));
+        assert_eq!(fuzzy_bitmap.lookup_best(0b10, 1).found.unwrap().value, &2);
+        let lookup = fuzzy_bitmap.lookup(0b11100, 0, &mut rng);
+        assert_eq!(lookup.found, None);
+        assert_eq!(lookup.considered, 0);
+    }
+
+    #[test]
+    fn test_lookup_rejected() {
+        // with a low chance we usually reject closer matches
+        let fuzzy_bitmap =
+            fuzzy_bitmap_with_policy(&[0b00, 0b01, 0b11], 2, MatchPolicy::Chance(0.1));
+        let mut rng = SmallRng::from_seed([0; 32]);
+        let mut ranks = [0; 3];
+        for _ in 0..100 {
+            let lookup = fuzzy_bitmap.lookup(0, 0, &mut rng);
+            if let Some(found) = lookup.found {
+                assert_eq!(lookup.considered, found.rank + 1);
+                assert_eq!(found.distance as usize, found.rank);
+                ranks[found.rank] += 1;
+            } else {
+                assert_eq!(lookup.considered, 3);
+            }
+        }
+        assert!(ranks.iter().all(|&count| count > 0));
+    }
+
+    proptest! {
+        #[test]
+        fn test_lookup_like_matching(
+            data in prop::collection::vec(any::<u32>().prop_map(|p| p & 0x0000_00ff), 0..32),
+            pattern in any::<u32>().prop_map(|p| p & 0x0000_00ff),
+            max_distance in 0..=8u32,
+            index in 0..40usize,
+            policy in prop_oneof![
+                (0.0..=1.0f64).prop_map(MatchPolicy::Chance),
+                Just(MatchPolicy::Best),
+                (0.0..=1.0f64).prop_map(MatchPolicy::Decay),
+                (0.0..4.0f64).prop_map(|temperature| MatchPolicy::Softmax { temperature }),
+                Just(MatchPolicy::Roulette),
+            ],
+            seed in any::<u64>(),
+        ) {
+            let fuzzy_bitmap = fuzzy_bitmap_with_policy(&data, max_distance, policy);
+            let matching = fuzzy_bitmap.matching(pattern, index);
+            let lookup = fuzzy_bitmap.lookup(pattern, index, &mut SmallRng::seed_from_u64(seed));
+            // get picks the same value as lookup
+            let value = fuzzy_bitmap.get(pattern, index, &mut SmallRng::seed_from_u64(seed));
+            prop_assert_eq!(lookup.found.as_ref().map(|found| found.value), value);
+            prop_assert!(lookup.considered <= matching.len());
+            if let Some(found) = lookup.found {
+                prop_assert_eq!(matching[found.rank], found.value);
+                prop_assert_eq!(found.distance, hamming_distance(pattern, data[*found.value]));
+                prop_assert!(lookup.considered > 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_insert_at_and_remove() {
+        let mut fuzzy_bitmap = FuzzyBitMap::new(1, 1.0);
+        fuzzy_bitmap.insert(0b00, 0);
+        fuzzy_bitmap.insert(0b01, 1);
+        fuzzy_bitmap.insert_at(1, 0b10, 2);
+        assert_eq!(fuzzy_bitmap.matching(0b00, 0), vec![&0, &2, &1]);
+        assert_eq!(fuzzy_bitmap.matching(0b00, 2), vec![&1]);
+        assert_eq!(fuzzy_bitmap.remove(0), (Masked::from(0b00), 0));
+        assert_eq!(fuzzy_bitmap.matching(0b00, 0), vec![&2, &1]);
+        assert_eq!(fuzzy_bitmap.entry(1), Some((Masked::from(0b01), &1)));
+        assert_eq!(fuzzy_bitmap.len(), 2);
+    }
+
+    #[test]
+    fn test_insert_and_remove_many() {
+        let mut fuzzy_bitmap = FuzzyBitMap::new(1, 1.0);
+        fuzzy_bitmap.insert(0b00, 0);
+        fuzzy_bitmap.insert(0b01, 1);
+        // the indexes are where the patterns end up
+        fuzzy_bitmap.insert_many([
+            (0, Masked::from(0b10), 2),
+            (2, Masked::from(0b11), 3),
+            (4, Masked::from(0b00), 4),
+        ]);
+        assert_eq!(
+            fuzzy_bitmap
+                .iter()
+                .map(|(_, &value)| value)
+                .collect::<Vec<_>>(),
+            vec![2, 0, 3, 1, 4]
+        );
+        assert_eq!(fuzzy_bitmap.matching(0b00, 0), vec![&0, &4, &2, &1]);
+        assert_eq!(
+            fuzzy_bitmap.remove_many([0, 3]),
+            vec![(Masked::from(0b10), 2), (Masked::from(0b01), 1)]
+        );
+        assert_eq!(fuzzy_bitmap.matching(0b00, 0), vec![&0, &4]);
+        assert_eq!(fuzzy_bitmap.matching(0b00, 1), vec![&4]);
+    }
+
+    #[test]
+    fn test_set_pattern() {
+        let mut fuzzy_bitmap = FuzzyBitMap::new(0, 1.0);
+        fuzzy_bitmap.insert(0b00, 0);
+        fuzzy_bitmap.insert(0b01, 1);
+        fuzzy_bitmap.set_pattern(0, 0b11);
+        assert!(fuzzy_bitmap.matching(0b00, 0).is_empty());
+        assert_eq!(fuzzy_bitmap.matching(0b11, 0), vec![&0]);
+        assert_eq!(
+            fuzzy_bitmap.iter().collect::<Vec<_>>(),
+            vec![(Masked::from(0b11), &0), (Masked::from(0b01), &1)]
+        );
+    }
+
+    #[derive(Debug, Clone)]
+    enum Edit {
+        Insert(usize, u32),
+        Remove(usize),
+        SetPattern(usize, u32),
+    }
+
+    proptest! {
+        #[test]
+        fn test_edits_like_scan(
+            data in prop::collection::vec(any::<u32>().prop_map(|p| p & 0x8421_ff00), 0..32),
+            edits in prop::collection::vec(
+                prop_oneof![
+                    (any::<usize>(), any::<u32>()).prop_map(|(i, p)| Edit::Insert(i, p & 0x8421_ff00)),
+                    any::<usize>().prop_map(Edit::Remove),
+                    (any::<usize>(), any::<u32>()).prop_map(|(i, p)| Edit::SetPattern(i, p & 0x8421_ff00)),
+                ],
+                0..32,
+            ),
+            pattern in any::<u32>().prop_map(|p| p & 0x8421_ff00),
+            max_distance in 0..=33u32,
+            index in 0..70usize,
+        ) {
+            let mut fuzzy_bitmap = FuzzyBitMap::new(max_distance, 0.5);
+            // the patterns and values we expect, with values numbered in
+            // the order they were inserted
+            let mut patterns = Vec::new();
+            let mut values = Vec::new();
+            for (value, &stored_pattern) in data.iter().enumerate() {
+                fuzzy_bitmap.insert(stored_pattern, value);
+                patterns.push(stored_pattern);
+                values.push(value);
+            }
+            let mut next_value = data.len();
+            for edit in edits {
+                match edit {
+                    Edit::Insert(i, p) => {
+                        let i = i % (patterns.len() + 1);
+                        fuzzy_bitmap.insert_at(i, p, next_value);
+                        patterns.insert(i, p);
+                        values.insert(i, next_value);
+                        next_value += 1;
+                    }
+                    Edit::Remove(i) if !patterns.is_empty() => {
+                        let i = i % patterns.len();
+                        let removed = (patterns.remove(i), values.remove(i));
+                        prop_assert_eq!(fuzzy_bitmap.remove(i), (Masked::from(removed.0), removed.1));
+                    }
+                    Edit::SetPattern(i, p) if !patterns.is_empty() => {
+                        let i = i % patterns.len();
+                        fuzzy_bitmap.set_pattern(i, p);
+                        patterns[i] = p;
+                    }
+                    _ => {}
+                }
+            }
+            let matching = fuzzy_bitmap
+                .iter_matching(pattern, index)
+                .copied()
+                .collect::<Vec<_>>();
+            let indexes = scan(&Hamming, &patterns, max_distance, pattern, index);
+            prop_assert_eq!(
+                matching,
+                indexes.into_iter().map(|i| values[i]).collect::<Vec<_>>()
+            );
+        }
+    }
 }
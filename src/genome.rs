@@ -0,0 +1,243 @@
+use std::ops::Range;
+
+use crate::assembler::{assemble, AssembleError};
+use crate::structure::Strand;
+
+/// A genome is a program made up of one or more strands of bytes. Each
+/// strand is split into blocks by zero bytes. Any bytes are a valid genome.
+///
+/// A genome owns its bytes, and keeps track of where its blocks are as it
+/// is mutated.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Genome {
+    strands: Vec<Vec<u8>>,
+    // for each strand, the ranges of its blocks, without the zero bytes
+    blocks: Vec<Vec<Range<usize>>>,
+}
+
+impl Genome {
+    /// Create a genome from the bytes of its strands.
+    pub fn new(strands: Vec<Vec<u8>>) -> Genome {
+        let blocks = strands.iter().map(|bytes| block_ranges(bytes)).collect();
+        Genome { strands, blocks }
+    }
+
+    /// Create a genome with a single strand.
+    pub fn from_bytes(bytes: &[u8]) -> Genome {
+        Genome::new(vec![bytes.to_vec()])
+    }
+
+    /// Assemble a genome from the assembly source of each strand.
+    pub fn assemble(sources: &[&str]) -> Result<Genome, AssembleError> {
+        let strands = sources
+            .iter()
+            .map(|source| assemble(source))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Genome::new(strands))
+    }
+
+    /// The bytes of each strand.
+    pub fn strands(&self) -> &[Vec<u8>] {
+        &self.strands
+    }
+
+    /// The total amount of bytes in all strands.
+    pub fn len(&self) -> usize {
+        self.strands.iter().map(|bytes| bytes.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The amount of blocks in a strand.
+    pub fn block_count(&self, strand_id: usize) -> usize {
+        self.blocks[strand_id].len()
+    }
+
+    /// The range of a block in the bytes of its strand, without the zero
+    /// byte that separates it from the next block.
+    pub fn block_range(&self, strand_id: usize, block_index: usize) -> Range<usize> {
+        self.blocks[strand_id][block_index].clone()
+    }
+
+    /// The bytes of a block.
+    pub fn block(&self, strand_id: usize, block_index: usize) -> &[u8] {
+        &self.strands[strand_id][self.block_range(strand_id, block_index)]
+    }
+
+    /// Set a single byte.
+    pub fn set_byte(&mut self, strand_id: usize, index: usize, byte: u8) {
+        let bytes = &mut self.strands[strand_id];
+        let previous = std::mem::replace(&mut bytes[index], byte);
+        // the blocks only change if a zero byte appears or disappears
+        if (previous == 0) != (byte == 0) {
+            self.update_blocks(strand_id);
+        }
+    }
+
+    /// Insert bytes before index.
+    pub fn insert_bytes(&mut self, strand_id: usize, index: usize, bytes: &[u8]) {
+        self.strands[strand_id].splice(index..index, bytes.iter().copied());
+        self.update_blocks(strand_id);
+    }
+
+    /// Remove a range of bytes.
+    pub fn remove_bytes(&mut self, strand_id: usize, range: Range<usize>) {
+        self.strands[strand_id].drain(range);
+        self.update_blocks(strand_id);
+    }
+
+    /// Replace all bytes of a strand.
+    pub fn set_strand(&mut self, strand_id: usize, bytes: Vec<u8>) {
+        self.strands[strand_id] = bytes;
+        self.update_blocks(strand_id);
+    }
+
+    /// Add a strand at the end.
+    pub fn push_strand(&mut self, bytes: Vec<u8>) {
+        self.blocks.push(block_ranges(&bytes));
+        self.strands.push(bytes);
+    }
+
+    /// Remove a strand, returning its bytes.
+    pub fn remove_strand(&mut self, strand_id: usize) -> Vec<u8> {
+        self.blocks.remove(strand_id);
+        self.strands.remove(strand_id)
+    }
+
+    fn update_blocks(&mut self, strand_id: usize) {
+        self.blocks[strand_id] = block_ranges(&self.strands[strand_id]);
+    }
+
+    pub(crate) fn strand_views(&self) -> Vec<Strand<'_>> {
+        self.strands
+            .iter()
+            .zip(&self.blocks)
+            .map(|(bytes, ranges)| Strand::from_block_ranges(bytes, ranges))
+            .collect()
+    }
+}
+
+// split bytes into blocks at zero bytes, like slice::split does
+fn block_ranges(bytes: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for (index, &byte) in bytes.iter().enumerate() {
+        if byte == 0 {
+            ranges.push(start..index);
+            start = index + 1;
+        }
+    }
+    ranges.push(start..bytes.len());
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn block_slices(genome: &Genome, strand_id: usize) -> Vec<&[u8]> {
+        (0..genome.block_count(strand_id))
+            .map(|block_index| genome.block(strand_id, block_index))
+            .collect()
+    }
+
+    fn split(bytes: &[u8]) -> Vec<&[u8]> {
+        bytes.split(|&byte| byte == 0).collect()
+    }
+
+    #[test]
+    fn test_assemble() {
+        let genome = Genome::assemble(&["inc r0\nblock\ninc r1", "inc r2"]).unwrap();
+        assert_eq!(genome.strands(), &[vec![0x18, 0x00, 0x19], vec![0x1A]]);
+        let strands = genome.strand_views();
+        assert_eq!(strands.len(), 2);
+        assert_eq!(strands[0].blocks().len(), 2);
+    }
+
+    #[test]
+    fn test_assemble_error() {
+        let error = Genome::assemble(&["inc r0", "jump"]).unwrap_err();
+        assert_eq!(error.line, 1);
+    }
+
+    #[test]
+    fn test_blocks() {
+        let genome = Genome::from_bytes(&[0x18, 0x00, 0x19, 0x1A, 0x00]);
+        assert_eq!(genome.block_count(0), 3);
+        assert_eq!(genome.block_range(0, 1), 2..4);
+        assert_eq!(
+            block_slices(&genome, 0),
+            vec![&[0x18][..], &[0x19, 0x1A], &[]]
+        );
+    }
+
+    #[test]
+    fn test_set_byte_splits_and_merges_blocks() {
+        let mut genome = Genome::from_bytes(&[0x18, 0x19, 0x1A]);
+        genome.set_byte(0, 1, 0x00);
+        assert_eq!(block_slices(&genome, 0), vec![&[0x18][..], &[0x1A]]);
+        genome.set_byte(0, 2, 0x1B);
+        assert_eq!(block_slices(&genome, 0), vec![&[0x18][..], &[0x1B]]);
+        genome.set_byte(0, 1, 0x19);
+        assert_eq!(block_slices(&genome, 0), vec![&[0x18, 0x19, 0x1B][..]]);
+    }
+
+    #[test]
+    fn test_insert_remove_bytes() {
+        let mut genome = Genome::from_bytes(&[0x18, 0x19]);
+        genome.insert_bytes(0, 1, &[0x00, 0x1A]);
+        assert_eq!(genome.strands(), &[vec![0x18, 0x00, 0x1A, 0x19]]);
+        assert_eq!(block_slices(&genome, 0), vec![&[0x18][..], &[0x1A, 0x19]]);
+        genome.remove_bytes(0, 0..2);
+        assert_eq!(block_slices(&genome, 0), vec![&[0x1A, 0x19][..]]);
+    }
+
+    #[test]
+    fn test_strands() {
+        let mut genome = Genome::from_bytes(&[0x18]);
+        genome.push_strand(vec![0x19, 0x00]);
+        assert_eq!(genome.len(), 3);
+        assert_eq!(genome.block_count(1), 2);
+        genome.set_strand(0, vec![0x00, 0x00]);
+        assert_eq!(genome.block_count(0), 3);
+        assert_eq!(genome.remove_strand(0), vec![0x00, 0x00]);
+        assert_eq!(genome.strands(), &[vec![0x19, 0x00]]);
+        assert_eq!(genome.block_count(0), 2);
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync + 'static>() {}
+        assert_send_sync::<Genome>();
+    }
+
+    proptest! {
+        #[test]
+        fn test_blocks_match_split(
+            bytes in prop::collection::vec(0..4u8, 0..32),
+            edits in prop::collection::vec((any::<prop::sample::Index>(), 0..4u8), 0..8),
+        ) {
+            let mut genome = Genome::from_bytes(&bytes);
+            prop_assert_eq!(block_slices(&genome, 0), split(&bytes));
+            for (index, byte) in edits {
+                if genome.is_empty() {
+                    break;
+                }
+                let index = index.index(genome.len());
+                genome.set_byte(0, index, byte);
+                prop_assert_eq!(block_slices(&genome, 0), split(&genome.strands()[0]));
+            }
+            let strands = genome.strand_views();
+            let reference = Strand::from_bytes(&genome.strands()[0]);
+            prop_assert_eq!(strands[0].blocks().len(), reference.blocks().len());
+            for (block, expected) in strands[0].blocks().iter().zip(reference.blocks()) {
+                prop_assert_eq!(block.pattern(), expected.pattern());
+                prop_assert_eq!(block.slice(), expected.slice());
+            }
+        }
+    }
+}
@@ -1,20 +1,25 @@
+use std::fmt;
+
 use crate::{blockid::BlockId, blockpattern::BlockPattern};
 
 #[derive(Debug, PartialEq, Eq, Hash)]
-struct RegisterId(u8);
+pub struct RegisterId(pub u8);
 
-// block references start out as a pattern reference,
-// and are then resolved to a block id.
+/// block references start out as a pattern reference,
+/// and are then resolved to a block id.
 #[derive(Debug, PartialEq, Eq)]
-enum BlockRef {
+pub enum BlockRef {
     Pattern(BlockPattern),
     Id(BlockId),
 }
 
 #[derive(Debug, PartialEq, Eq)]
-enum Instruction {
+pub enum Instruction {
+    /// a zero byte separates blocks
+    Block,
     Call(BlockRef),
     Return,
+    Value(RegisterId, u32),
     If(RegisterId),
     Repeat(RegisterId),
     Not(RegisterId),
@@ -35,85 +40,341 @@ enum Instruction {
     Xor(RegisterId, RegisterId),
     Unknown0(RegisterId, RegisterId),
     Unknown1(RegisterId, RegisterId),
+    /// a pattern byte contributes its low nibble to a pattern
+    Pattern(u8),
+}
+
+// the operation of a single byte. unlike an instruction, it doesn't include
+// the pattern that call and value take from the bytes before them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    Block,
+    Call,
+    Return,
+    Value,
+    If(u8),
+    Repeat(u8),
+    Not(u8),
+    Push(u8),
+    Pop(u8),
+    Inc(u8),
+    Dec(u8),
+    Store(u8, u8),
+    Load(u8, u8),
+    Add(u8, u8),
+    Sub(u8, u8),
+    Mul(u8, u8),
+    Div(u8, u8),
+    Eq(u8, u8),
+    Gt(u8, u8),
+    And(u8, u8),
+    Or(u8, u8),
+    Xor(u8, u8),
+    Unknown0(u8, u8),
+    Unknown1(u8, u8),
+    Pattern(u8),
+}
+
+// the decode table, with the operation for each byte
+static OPS: [Op; 256] = decode_table();
+
+const fn decode_table() -> [Op; 256] {
+    let mut table = [Op::Block; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        table[byte] = decode_op(byte as u8);
+        byte += 1;
+    }
+    table
+}
+
+// the byte layout of the instruction set. the high nibble is the opcode.
+// the low nibble is the operand, which mostly consists of one or two
+// two-bit registers.
+const fn decode_op(bytecode: u8) -> Op {
+    let opcode = bytecode >> 4;
+    let operand = bytecode & 0b0000_1111;
+    let r0 = (operand & 0b0000_1100) >> 2;
+    let r1 = operand & 0b0000_0011;
+    match opcode {
+        0 => match operand {
+            0 => Op::Block,
+            1 => Op::Call,
+            2 => Op::Return,
+            3 => Op::Value,
+            4..=7 => Op::If(r1),
+            8..=11 => Op::Repeat(r1),
+            _ => Op::Not(r1),
+        },
+        1 => match r0 {
+            0 => Op::Push(r1),
+            1 => Op::Pop(r1),
+            2 => Op::Inc(r1),
+            _ => Op::Dec(r1),
+        },
+        2 => Op::Store(r0, r1),
+        3 => Op::Load(r0, r1),
+        4 => Op::Add(r0, r1),
+        5 => Op::Sub(r0, r1),
+        6 => Op::Mul(r0, r1),
+        7 => Op::Div(r0, r1),
+        8 => Op::Eq(r0, r1),
+        9 => Op::Gt(r0, r1),
+        10 => Op::And(r0, r1),
+        11 => Op::Or(r0, r1),
+        12 => Op::Xor(r0, r1),
+        13 => Op::Unknown0(r0, r1),
+        14 => Op::Unknown1(r0, r1),
+        _ => Op::Pattern(operand),
+    }
+}
+
+impl Op {
+    pub(crate) fn decode(bytecode: u8) -> Op {
+        OPS[bytecode as usize]
+    }
 }
 
 impl Instruction {
-    fn decode(slice: &[u8], index: usize) -> Instruction {
-        let bytecode = slice[index];
-        let opcode = bytecode >> 4;
-        match opcode {
-            0 => {
-                let operand = bytecode & 0b0000_1111;
-                match operand {
-                    0 => {
-                        unreachable!()
-                    }
-                    1 => {
-                        let block_pattern = BlockPattern::decode_backward(slice, index);
-                        Instruction::Call(BlockRef::Pattern(block_pattern))
-                    }
-                    2 => Instruction::Return,
-                    3 => todo!(),
-                    4..=7 => {
-                        let register = RegisterId(operand & 0b0000_0011);
-                        Instruction::If(register)
-                    }
-                    8..=11 => {
-                        let register = RegisterId(operand & 0b0000_0011);
-                        Instruction::Repeat(register)
-                    }
-                    12..=15 => {
-                        let register = RegisterId(operand & 0b0000_0011);
-                        Instruction::Not(register)
-                    }
-                    _ => unreachable!(),
-                }
+    /// decoding is total: every byte at every index decodes to an instruction.
+    /// beyond the end of the slice the block has ended.
+    pub fn decode(slice: &[u8], index: usize) -> Instruction {
+        let bytecode = match slice.get(index) {
+            Some(&bytecode) => bytecode,
+            None => return Instruction::Block,
+        };
+        match Op::decode(bytecode) {
+            Op::Block => Instruction::Block,
+            Op::Call => {
+                let block_pattern = BlockPattern::decode_backward(slice, index);
+                Instruction::Call(BlockRef::Pattern(block_pattern))
             }
-            1 => {
-                let register = RegisterId(bytecode & 0b0000_0011);
-                match opcode {
-                    0..=3 => Instruction::Push(register),
-                    4..=7 => Instruction::Pop(register),
-                    8..=11 => Instruction::Inc(register),
-                    12..=15 => Instruction::Dec(register),
-                    _ => unreachable!(),
+            Op::Return => Instruction::Return,
+            Op::Value => {
+                let block_pattern = BlockPattern::decode_backward(slice, index);
+                let (register, value) = split_value_pattern(&block_pattern);
+                Instruction::Value(RegisterId(register), value)
+            }
+            Op::If(r) => Instruction::If(RegisterId(r)),
+            Op::Repeat(r) => Instruction::Repeat(RegisterId(r)),
+            Op::Not(r) => Instruction::Not(RegisterId(r)),
+            Op::Push(r) => Instruction::Push(RegisterId(r)),
+            Op::Pop(r) => Instruction::Pop(RegisterId(r)),
+            Op::Inc(r) => Instruction::Inc(RegisterId(r)),
+            Op::Dec(r) => Instruction::Dec(RegisterId(r)),
+            Op::Store(r0, r1) => Instruction::Store(RegisterId(r0), RegisterId(r1)),
+            Op::Load(r0, r1) => Instruction::Load(RegisterId(r0), RegisterId(r1)),
+            Op::Add(r0, r1) => Instruction::Add(RegisterId(r0), RegisterId(r1)),
+            Op::Sub(r0, r1) => Instruction::Sub(RegisterId(r0), RegisterId(r1)),
+            Op::Mul(r0, r1) => Instruction::Mul(RegisterId(r0), RegisterId(r1)),
+            Op::Div(r0, r1) => Instruction::Div(RegisterId(r0), RegisterId(r1)),
+            Op::Eq(r0, r1) => Instruction::Eq(RegisterId(r0), RegisterId(r1)),
+            Op::Gt(r0, r1) => Instruction::Gt(RegisterId(r0), RegisterId(r1)),
+            Op::And(r0, r1) => Instruction::And(RegisterId(r0), RegisterId(r1)),
+            Op::Or(r0, r1) => Instruction::Or(RegisterId(r0), RegisterId(r1)),
+            Op::Xor(r0, r1) => Instruction::Xor(RegisterId(r0), RegisterId(r1)),
+            Op::Unknown0(r0, r1) => Instruction::Unknown0(RegisterId(r0), RegisterId(r1)),
+            Op::Unknown1(r0, r1) => Instruction::Unknown1(RegisterId(r0), RegisterId(r1)),
+            Op::Pattern(nibble) => Instruction::Pattern(nibble),
+        }
+    }
+}
+
+impl Instruction {
+    /// encode the instruction, so that decoding the last byte of the encoding
+    /// results in the instruction again. call and value are preceded by
+    /// pattern bytes. the wildcards of a call are encoded before its pattern,
+    /// where only masked patterns look for them.
+    ///
+    /// instructions that have no encoding are an error, and leave the bytes
+    /// untouched: a call to a block id, a register beyond r3, a pattern
+    /// nibble beyond 0xf and a value beyond 30 bits.
+    pub fn encode(&self, bytes: &mut Vec<u8>) -> Result<(), EncodeError> {
+        let bytecode = self.bytecode()?;
+        match self {
+            Instruction::Call(BlockRef::Pattern(block_pattern)) => {
+                let pattern_bytes = block_pattern.to_pattern_bytes();
+                let length = block_pattern.length();
+                if block_pattern.wildcards() != 0 {
+                    bytes.extend(BlockPattern::new(block_pattern.wildcards()).to_pattern_bytes());
+                    bytes.extend(pattern_bytes);
+                } else if block_pattern.present() == ((1u64 << length) - 1) as u32 {
+                    // only the lowest bytes are present, as in a pattern
+                    // decoded from the start of a block, so only encode those
+                    bytes.extend(&pattern_bytes[8 - length as usize / 4..]);
+                } else {
+                    bytes.extend(pattern_bytes);
                 }
             }
-            2..=14 => {
-                let operand = bytecode & 0b0000_1111;
-                let r0 = RegisterId((operand & 0b0000_1100) >> 2);
-                let r1 = RegisterId(operand & 0b0000_0011);
-                match opcode {
-                    2 => Instruction::Store(r0, r1),
-                    3 => Instruction::Load(r0, r1),
-                    4 => Instruction::Add(r0, r1),
-                    5 => Instruction::Sub(r0, r1),
-                    6 => Instruction::Mul(r0, r1),
-                    7 => Instruction::Div(r0, r1),
-                    8 => Instruction::Eq(r0, r1),
-                    9 => Instruction::Gt(r0, r1),
-                    10 => Instruction::And(r0, r1),
-                    11 => Instruction::Or(r0, r1),
-                    12 => Instruction::Xor(r0, r1),
-                    13 => Instruction::Unknown0(r0, r1),
-                    14 => Instruction::Unknown1(r0, r1),
-                    _ => unreachable!(),
+            Instruction::Value(r, value) => {
+                bytes.extend(join_value_pattern(r.0, *value).to_pattern_bytes());
+            }
+            _ => {}
+        }
+        bytes.push(bytecode);
+        Ok(())
+    }
+
+    /// the byte of the instruction itself, without the pattern bytes
+    /// call and value need
+    pub fn bytecode(&self) -> Result<u8, EncodeError> {
+        let bytecode = match self {
+            Instruction::Block => 0,
+            Instruction::Call(BlockRef::Pattern(_)) => 0b0000_0001,
+            Instruction::Call(BlockRef::Id(_)) => return Err(EncodeError::UnresolvedCall),
+            Instruction::Return => 0b0000_0010,
+            Instruction::Value(r, value) => {
+                check_register(r)?;
+                if *value > MAX_VALUE {
+                    return Err(EncodeError::ValueOutOfRange(*value));
                 }
+                0b0000_0011
+            }
+            Instruction::If(r) => 0b0000_0100 | check_register(r)?,
+            Instruction::Repeat(r) => 0b0000_1000 | check_register(r)?,
+            Instruction::Not(r) => 0b0000_1100 | check_register(r)?,
+            Instruction::Push(r) => 0b0001_0000 | check_register(r)?,
+            Instruction::Pop(r) => 0b0001_0100 | check_register(r)?,
+            Instruction::Inc(r) => 0b0001_1000 | check_register(r)?,
+            Instruction::Dec(r) => 0b0001_1100 | check_register(r)?,
+            Instruction::Store(r0, r1) => encode_registers(2, r0, r1)?,
+            Instruction::Load(r0, r1) => encode_registers(3, r0, r1)?,
+            Instruction::Add(r0, r1) => encode_registers(4, r0, r1)?,
+            Instruction::Sub(r0, r1) => encode_registers(5, r0, r1)?,
+            Instruction::Mul(r0, r1) => encode_registers(6, r0, r1)?,
+            Instruction::Div(r0, r1) => encode_registers(7, r0, r1)?,
+            Instruction::Eq(r0, r1) => encode_registers(8, r0, r1)?,
+            Instruction::Gt(r0, r1) => encode_registers(9, r0, r1)?,
+            Instruction::And(r0, r1) => encode_registers(10, r0, r1)?,
+            Instruction::Or(r0, r1) => encode_registers(11, r0, r1)?,
+            Instruction::Xor(r0, r1) => encode_registers(12, r0, r1)?,
+            Instruction::Unknown0(r0, r1) => encode_registers(13, r0, r1)?,
+            Instruction::Unknown1(r0, r1) => encode_registers(14, r0, r1)?,
+            Instruction::Pattern(nibble) if *nibble > 0b1111 => {
+                return Err(EncodeError::PatternOutOfRange(*nibble))
             }
-            15 => {
-                todo!()
+            Instruction::Pattern(nibble) => 0b1111_0000 | nibble,
+        };
+        Ok(bytecode)
+    }
+}
+
+/// The reason an instruction can't be encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeError {
+    /// a call to a block id, which only exists once a program is linked
+    UnresolvedCall,
+    InvalidRegister(u8),
+    /// a pattern byte only holds a nibble
+    PatternOutOfRange(u8),
+    /// a value only has the 30 bits of its pattern that don't select the
+    /// register
+    ValueOutOfRange(u32),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::UnresolvedCall => write!(f, "a call to a block id cannot be encoded"),
+            EncodeError::InvalidRegister(register) => write!(f, "invalid register r{}", register),
+            EncodeError::PatternOutOfRange(nibble) => {
+                write!(f, "pattern nibble out of range {:#x}", nibble)
+            }
+            EncodeError::ValueOutOfRange(value) => write!(f, "value out of range {}", value),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+impl fmt::Display for RegisterId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "r{}", self.0)
+    }
+}
+
+// instructions are displayed in the assembly language
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Block => write!(f, "block"),
+            Instruction::Call(BlockRef::Pattern(block_pattern)) => {
+                write!(f, "call {}", block_pattern)
             }
-            _ => unreachable!(),
+            Instruction::Call(BlockRef::Id(block_id)) => write!(f, "call {}", block_id),
+            Instruction::Return => write!(f, "return"),
+            Instruction::Value(r, value) => write!(f, "value {} {}", r, value),
+            Instruction::If(r) => write!(f, "if {}", r),
+            Instruction::Repeat(r) => write!(f, "repeat {}", r),
+            Instruction::Not(r) => write!(f, "not {}", r),
+            Instruction::Push(r) => write!(f, "push {}", r),
+            Instruction::Pop(r) => write!(f, "pop {}", r),
+            Instruction::Inc(r) => write!(f, "inc {}", r),
+            Instruction::Dec(r) => write!(f, "dec {}", r),
+            Instruction::Store(r0, r1) => write!(f, "store {} {}", r0, r1),
+            Instruction::Load(r0, r1) => write!(f, "load {} {}", r0, r1),
+            Instruction::Add(r0, r1) => write!(f, "add {} {}", r0, r1),
+            Instruction::Sub(r0, r1) => write!(f, "sub {} {}", r0, r1),
+            Instruction::Mul(r0, r1) => write!(f, "mul {} {}", r0, r1),
+            Instruction::Div(r0, r1) => write!(f, "div {} {}", r0, r1),
+            Instruction::Eq(r0, r1) => write!(f, "eq {} {}", r0, r1),
+            Instruction::Gt(r0, r1) => write!(f, "gt {} {}", r0, r1),
+            Instruction::And(r0, r1) => write!(f, "and {} {}", r0, r1),
+            Instruction::Or(r0, r1) => write!(f, "or {} {}", r0, r1),
+            Instruction::Xor(r0, r1) => write!(f, "xor {} {}", r0, r1),
+            Instruction::Unknown0(r0, r1) => write!(f, "unknown0 {} {}", r0, r1),
+            Instruction::Unknown1(r0, r1) => write!(f, "unknown1 {} {}", r0, r1),
+            Instruction::Pattern(nibble) => write!(f, "pattern {:#x}", nibble),
         }
     }
 }
 
+// the largest value a value instruction can load
+const MAX_VALUE: u32 = u32::MAX >> 2;
+
+fn check_register(r: &RegisterId) -> Result<u8, EncodeError> {
+    if r.0 > 3 {
+        return Err(EncodeError::InvalidRegister(r.0));
+    }
+    Ok(r.0)
+}
+
+fn encode_registers(opcode: u8, r0: &RegisterId, r1: &RegisterId) -> Result<u8, EncodeError> {
+    Ok(opcode << 4 | check_register(r0)? << 2 | check_register(r1)?)
+}
+
+// the value instruction loads a value from the pattern before it into a
+// register. the lowest two bits of the pattern select the register, the
+// remaining bits are the value.
+pub(crate) fn split_value_pattern(block_pattern: &BlockPattern) -> (u8, u32) {
+    let pattern = block_pattern.get();
+    ((pattern & 0b0000_0011) as u8, pattern >> 2)
+}
+
+// the inverse of split_value_pattern. only the lowest 30 bits of the value
+// can be encoded.
+pub(crate) fn join_value_pattern(register: u8, value: u32) -> BlockPattern {
+    BlockPattern::new(value << 2 | register as u32)
+}
+
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use crate::blockpattern::BlockPattern;
 
     use super::*;
 
+    fn encode(instruction: &Instruction) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        instruction.encode(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn decode_last(bytes: &[u8]) -> Instruction {
+        Instruction::decode(bytes, bytes.len() - 1)
+    }
+
     #[test]
     fn test_decode_call() {
         let data = [
@@ -159,4 +420,202 @@ mod tests {
         let instruction = Instruction::decode(&data, 0);
         assert_eq!(instruction, Instruction::Add(RegisterId(0), RegisterId(1)));
     }
+
+    #[test]
+    fn test_decode_value() {
+        let data = [
+            0b1111_0001,
+            0b1111_0010,
+            0b1111_0011,
+            0b1111_0100,
+            0b1111_0101,
+            0b1111_0110,
+            0b1111_0111,
+            0b1111_1010,
+            // value
+            0b0000_0011,
+        ];
+        let index = data.len() - 1;
+        let instruction = Instruction::decode(&data, index);
+        assert_eq!(
+            instruction,
+            Instruction::Value(RegisterId(0b10), 0x1234_567A >> 2)
+        );
+    }
+
+    #[test]
+    fn test_decode_value_at_start_of_block() {
+        let data = [0b1111_0010, 0b1111_1011, 0b0000_0011];
+        let instruction = Instruction::decode(&data, 2);
+        assert_eq!(
+            instruction,
+            Instruction::Value(RegisterId(3), 0b0010_1011 >> 2)
+        );
+    }
+
+    #[test]
+    fn test_decode_push_pop_inc_dec() {
+        assert_eq!(
+            Instruction::decode(&[0b0001_0001], 0),
+            Instruction::Push(RegisterId(1))
+        );
+        assert_eq!(
+            Instruction::decode(&[0b0001_0110], 0),
+            Instruction::Pop(RegisterId(2))
+        );
+        assert_eq!(
+            Instruction::decode(&[0b0001_1011], 0),
+            Instruction::Inc(RegisterId(3))
+        );
+        assert_eq!(
+            Instruction::decode(&[0b0001_1100], 0),
+            Instruction::Dec(RegisterId(0))
+        );
+    }
+
+    #[test]
+    fn test_decode_two_registers() {
+        let data = [0b0010_1101];
+        let instruction = Instruction::decode(&data, 0);
+        assert_eq!(
+            instruction,
+            Instruction::Store(RegisterId(3), RegisterId(1))
+        );
+    }
+
+    #[test]
+    fn test_decode_block() {
+        assert_eq!(Instruction::decode(&[0], 0), Instruction::Block);
+    }
+
+    #[test]
+    fn test_decode_pattern() {
+        assert_eq!(
+            Instruction::decode(&[0b1111_0101], 0),
+            Instruction::Pattern(0b0101)
+        );
+    }
+
+    #[test]
+    fn test_encode_call() {
+        let instruction = Instruction::Call(BlockRef::Pattern(BlockPattern::new(
+            0b0101_1000_1010_1010_0101_0101_1000_0001,
+        )));
+        let bytes = encode(&instruction);
+        assert_eq!(bytes.len(), 9);
+        assert_eq!(decode_last(&bytes), instruction);
+    }
+
+    #[test]
+    fn test_encode_call_short_pattern() {
+        // a call near the start of a block has a pattern of fewer bytes
+        let bytes = [0xF1, 0xF2, 0x01];
+        let instruction = Instruction::decode(&bytes, 2);
+        assert_eq!(encode(&instruction), bytes);
+    }
+
+    #[test]
+    fn test_encode_call_wildcards() {
+        let pattern = BlockPattern::new(0x1234_0000).with_wildcards(0x0000_ffff);
+        let bytes = encode(&Instruction::Call(BlockRef::Pattern(pattern)));
+        assert_eq!(bytes.len(), 17);
+        assert_eq!(
+            BlockPattern::decode_backward_masked(&bytes, bytes.len() - 1),
+            pattern
+        );
+    }
+
+    #[test]
+    fn test_encode_value() {
+        let instruction = Instruction::Value(RegisterId(2), 42);
+        let bytes = encode(&instruction);
+        assert_eq!(decode_last(&bytes), instruction);
+    }
+
+    #[test]
+    fn test_encode_registers() {
+        assert_eq!(
+            encode(&Instruction::Store(RegisterId(3), RegisterId(1))),
+            vec![0b0010_1101]
+        );
+        assert_eq!(encode(&Instruction::Dec(RegisterId(2))), vec![0b0001_1110]);
+    }
+
+    #[test]
+    fn test_encode_invalid() {
+        let invalid = [
+            (
+                Instruction::Call(BlockRef::Id(BlockId::new(0, 1))),
+                EncodeError::UnresolvedCall,
+            ),
+            (
+                Instruction::If(RegisterId(4)),
+                EncodeError::InvalidRegister(4),
+            ),
+            (
+                Instruction::Add(RegisterId(0), RegisterId(7)),
+                EncodeError::InvalidRegister(7),
+            ),
+            (
+                Instruction::Value(RegisterId(9), 0),
+                EncodeError::InvalidRegister(9),
+            ),
+            (
+                Instruction::Value(RegisterId(0), 1 << 30),
+                EncodeError::ValueOutOfRange(1 << 30),
+            ),
+            (
+                Instruction::Pattern(0x10),
+                EncodeError::PatternOutOfRange(0x10),
+            ),
+        ];
+        for (instruction, error) in invalid {
+            let mut bytes = vec![0x18];
+            assert_eq!(instruction.encode(&mut bytes), Err(error));
+            assert_eq!(bytes, vec![0x18]);
+        }
+        // the largest value still fits
+        let instruction = Instruction::Value(RegisterId(3), (1 << 30) - 1);
+        assert_eq!(decode_last(&encode(&instruction)), instruction);
+    }
+
+    #[test]
+    fn test_encode_all_bytes() {
+        // every single byte encodes to itself, except for call and value,
+        // which gain pattern bytes
+        for byte in 0..=255 {
+            let instruction = Instruction::decode(&[byte], 0);
+            let bytes = encode(&instruction);
+            assert_eq!(bytes.last(), Some(&byte));
+            assert_eq!(decode_last(&bytes), instruction);
+        }
+    }
+
+    #[test]
+    fn test_decode_beyond_end() {
+        assert_eq!(Instruction::decode(&[], 0), Instruction::Block);
+        assert_eq!(Instruction::decode(&[0x18], 5), Instruction::Block);
+    }
+
+    proptest! {
+        #[test]
+        fn test_decode_every_index(data in prop::collection::vec(any::<u8>(), 0..64)) {
+            for index in 0..data.len() + 2 {
+                let instruction = Instruction::decode(&data, index);
+                if index >= data.len() {
+                    prop_assert_eq!(instruction, Instruction::Block);
+                }
+            }
+        }
+
+        #[test]
+        fn test_encode_decode_round_trip(
+            data in prop::collection::vec(any::<u8>(), 1..16),
+            index in any::<prop::sample::Index>(),
+        ) {
+            let instruction = Instruction::decode(&data, index.index(data.len()));
+            let bytes = encode(&instruction);
+            prop_assert_eq!(decode_last(&bytes), instruction);
+        }
+    }
 }
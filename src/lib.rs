@@ -1,6 +1,69 @@
+//! evoby is a virtual machine for linear genetic programming.
+//!
+//! A [`Genome`] consists of strands of bytes, and any bytes are a valid
+//! program. Strands are split into blocks by zero bytes. Each block is
+//! identified by a [`BlockPattern`] decoded from its first bytes. A call
+//! instruction takes a pattern from the bytes before it, and calls the block
+//! with the most similar pattern, as found by a [`FuzzyBitMap`]. This makes
+//! calls robust against mutation.
+//!
+//! A [`Machine`] runs a genome on inputs:
+//!
+//! ```
+//! use evoby::{ExecutionOutcome, Genome, Machine};
+//!
+//! let genome = Genome::assemble(&["
+//!     add r0 r1
+//!     call @0x0000_0001
+//!     block @0x0000_0001
+//!     inc r0
+//! "])
+//! .unwrap();
+//! let mut machine = Machine::new().match_chance(1.0);
+//! let output = machine.run(&genome, &[2, 3]);
+//! assert_eq!(output.execution.outcome, ExecutionOutcome::Finished);
+//! assert_eq!(output.registers[0], 6);
+//! ```
+
+mod assembler;
 mod blockid;
 mod blockpattern;
+mod compiler;
 mod core;
+mod crossover;
+mod disassembler;
+mod evolution;
 mod fuzzy;
+mod genome;
 mod instruction;
+mod linker;
+mod machine;
+mod metric;
+mod mutation;
 mod structure;
+
+pub use crate::assembler::{AssembleError, AssembleErrorKind};
+pub use crate::blockid::BlockId;
+pub use crate::blockpattern::{BlockPattern, Direction, Patterns};
+pub use crate::core::{Execution, ExecutionOutcome, Interpreter};
+pub use crate::crossover::{
+    BlockCrossover, Crossover, HomologousCrossover, OnePointCrossover, TwoPointCrossover,
+};
+pub use crate::disassembler::Resolution;
+pub use crate::evolution::{
+    strand_crossover, strand_mutation, Evolver, Fitness, GenerationStats, Population, Replacement,
+    Selection, SteadyState, StepStats,
+};
+pub use crate::fuzzy::{
+    hamming_distance, Found, FuzzyBitMap, Lookup, Masked, MatchPolicy, Matching,
+};
+pub use crate::genome::Genome;
+pub use crate::instruction::{BlockRef, EncodeError, Instruction, RegisterId};
+pub use crate::linker::{Binding, CallSite, Linking, Program};
+pub use crate::machine::{Machine, Output};
+pub use crate::metric::{AbsoluteDifference, Hamming, Metric, Streak, WeightedBits, Wrap};
+pub use crate::mutation::{
+    BlockDuplication, CallPatternFlip, InstructionIndel, Mutation, Mutations, NibbleMutation,
+    PointMutation, SeparatorIndel,
+};
+pub use crate::structure::{LookupStats, StrandBuilder};
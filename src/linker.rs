@@ -0,0 +1,271 @@
+// the linker lays out the blocks of all strands in instruction memory, and
+// finds the call sites in them. depending on the linking, call sites are
+// bound to the blocks they call ahead of execution, so that execution
+// doesn't need to search for them.
+
+use rand::Rng;
+
+use crate::blockid::BlockId;
+use crate::blockpattern::BlockPattern;
+use crate::compiler::{compile, Code};
+use crate::instruction::{BlockRef, Op};
+use crate::structure::{Blocks, LookupStats, Strand};
+
+/// How calls are bound to the blocks they call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Linking {
+    /// Every call looks for a matching block each time it is executed.
+    #[default]
+    Dynamic,
+    /// Every call site is bound to a matching block once per execution,
+    /// using the randomness of the execution.
+    Stochastic,
+    /// Every call site is bound to its best matching block once, when
+    /// linking, so execution doesn't depend on randomness.
+    Deterministic,
+}
+
+/// What a call site is bound to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Binding {
+    /// The call is resolved during execution.
+    Unbound,
+    /// No block matches, so the call does nothing.
+    Unresolved,
+    /// The call always calls this block.
+    Block(BlockId),
+}
+
+/// A call instruction in a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallSite {
+    /// The block the call is in.
+    pub block: BlockId,
+    /// The index of the call instruction in its block.
+    pub offset: usize,
+    /// The pattern before the call instruction.
+    pub pattern: BlockPattern,
+    pub binding: Binding,
+    // the index of the call instruction in instruction memory
+    index: usize,
+}
+
+impl CallSite {
+    /// The block this call site refers to: the block id if it is bound,
+    /// otherwise its pattern.
+    pub fn block_ref(&self) -> BlockRef {
+        match &self.binding {
+            Binding::Block(block_id) => BlockRef::Id(block_id.clone()),
+            _ => BlockRef::Pattern(self.pattern),
+        }
+    }
+}
+
+/// A linked program, ready to be executed.
+#[derive(Debug)]
+pub struct Program {
+    // the blocks of all strands, each block followed by a zero byte
+    pub(crate) instruction_memory: Vec<u8>,
+    // the instruction memory, pre-decoded
+    pub(crate) code: Vec<Code>,
+    // for each strand, the start of each block in instruction memory
+    pub(crate) block_starts: Vec<Vec<usize>>,
+    pub(crate) blocks: Blocks,
+    pub(crate) linking: Linking,
+    // sorted by index
+    call_sites: Vec<CallSite>,
+}
+
+pub(crate) fn link(strands: &[Strand], blocks: Blocks, linking: Linking) -> Program {
+    let mut instruction_memory = Vec::new();
+    let mut block_starts = Vec::new();
+    let mut call_sites = Vec::new();
+    for (strand_id, strand) in strands.iter().enumerate() {
+        let mut strand_block_starts = Vec::new();
+        for (block_index, slice) in strand.block_slices().enumerate() {
+            let block_start = instruction_memory.len();
+            strand_block_starts.push(block_start);
+            for (offset, &byte) in slice.iter().enumerate() {
+                if let Op::Call = Op::decode(byte) {
+                    call_sites.push(CallSite {
+                        block: BlockId::new(strand_id, block_index),
+                        offset,
+                        pattern: blocks.call_pattern(slice, offset),
+                        binding: Binding::Unbound,
+                        index: block_start + offset,
+                    });
+                }
+            }
+            instruction_memory.extend_from_slice(slice);
+            instruction_memory.push(0);
+        }
+        block_starts.push(strand_block_starts);
+    }
+    if linking == Linking::Deterministic {
+        for call_site in &mut call_sites {
+            call_site.binding =
+                bind(blocks.lookup_best(call_site.pattern, call_site.block.block_index()));
+        }
+    }
+    Program {
+        code: compile(&instruction_memory),
+        instruction_memory,
+        block_starts,
+        blocks,
+        linking,
+        call_sites,
+    }
+}
+
+// link edited strands again, updating the blocks of a program that was
+// linked before instead of building them anew
+pub(crate) fn relink(program: Program, strands: &[Strand], linking: Linking) -> Program {
+    let mut blocks = program.blocks;
+    blocks.update(strands);
+    link(strands, blocks, linking)
+}
+
+fn bind(target: Option<&BlockId>) -> Binding {
+    match target {
+        Some(target) => Binding::Block(target.clone()),
+        None => Binding::Unresolved,
+    }
+}
+
+impl Program {
+    /// How this program was linked.
+    pub fn linking(&self) -> Linking {
+        self.linking
+    }
+
+    /// How fuzzy the lookups of blocks were, both when linking and
+    /// during execution.
+    pub fn lookup_stats(&self) -> LookupStats {
+        self.blocks.lookup_stats()
+    }
+
+    /// All call sites, in the order of the strands and blocks.
+    pub fn call_sites(&self) -> &[CallSite] {
+        &self.call_sites
+    }
+
+    // bind every call site to a matching block, as it would be resolved
+    // during execution, and count the lookups in stats
+    pub(crate) fn bind_stochastic(
+        &self,
+        rng: &mut impl Rng,
+        stats: &mut LookupStats,
+    ) -> Vec<Binding> {
+        self.call_sites
+            .iter()
+            .map(|call_site| {
+                let lookup =
+                    self.blocks
+                        .lookup_match(call_site.pattern, call_site.block.block_index(), rng);
+                stats.record(&lookup);
+                bind(lookup.found.map(|found| found.value))
+            })
+            .collect()
+    }
+
+    // the position in call_sites of the call instruction at index
+    pub(crate) fn call_site_position(&self, index: usize) -> usize {
+        self.call_sites
+            .binary_search_by_key(&index, |call_site| call_site.index)
+            .expect("call instruction without call site")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use crate::assembler::assemble;
+
+    use super::*;
+
+    fn link_bytes(strands: &[&[u8]], match_chance: f64, linking: Linking) -> Program {
+        let strands = strands
+            .iter()
+            .map(|bytes| Strand::from_bytes(bytes))
+            .collect::<Vec<_>>();
+        let blocks = Blocks::new(2, match_chance, &strands);
+        link(&strands, blocks, linking)
+    }
+
+    #[test]
+    fn test_link_layout() {
+        let program = link_bytes(&[&[0x18, 0x00, 0x19], &[0x1A]], 1.0, Linking::Dynamic);
+        assert_eq!(
+            program.instruction_memory,
+            vec![0x18, 0x00, 0x19, 0x00, 0x1A, 0x00]
+        );
+        assert_eq!(program.block_starts, vec![vec![0, 2], vec![4]]);
+        assert!(program.call_sites().is_empty());
+    }
+
+    #[test]
+    fn test_link_dynamic() {
+        let bytes = assemble("inc r0\ncall @0x0000_0003\nblock @0x0000_0003").unwrap();
+        let program = link_bytes(&[&bytes], 1.0, Linking::Dynamic);
+        let call_sites = program.call_sites();
+        assert_eq!(call_sites.len(), 1);
+        assert_eq!(call_sites[0].block, BlockId::new(0, 0));
+        assert_eq!(call_sites[0].offset, 9);
+        assert_eq!(call_sites[0].binding, Binding::Unbound);
+        assert_eq!(
+            call_sites[0].block_ref(),
+            BlockRef::Pattern(BlockPattern::new(3))
+        );
+        assert_eq!(program.call_site_position(9), 0);
+    }
+
+    #[test]
+    fn test_link_deterministic() {
+        let entry = assemble("inc r0\ncall @0x0000_0003\ncall @0xffff_ffff").unwrap();
+        let other = assemble("block\nblock @0x0000_0001").unwrap();
+        // even though calls never accept a match during execution, linking
+        // deterministically binds to the best match
+        let program = link_bytes(&[&entry, &other], 0.0, Linking::Deterministic);
+        let call_sites = program.call_sites();
+        assert_eq!(call_sites.len(), 2);
+        assert_eq!(call_sites[0].binding, Binding::Block(BlockId::new(1, 1)));
+        assert_eq!(call_sites[0].block_ref(), BlockRef::Id(BlockId::new(1, 1)));
+        assert_eq!(call_sites[1].binding, Binding::Unresolved);
+    }
+
+    #[test]
+    fn test_relink() {
+        let entry = assemble("inc r0\ncall @0x0000_0003\nblock @0x0000_0001").unwrap();
+        let program = link_bytes(&[&entry], 1.0, Linking::Deterministic);
+        assert_eq!(
+            program.call_sites()[0].binding,
+            Binding::Block(BlockId::new(0, 1))
+        );
+        // a new strand has a closer block
+        let other = assemble("block @0x0000_0003").unwrap();
+        let strands = [Strand::from_bytes(&entry), Strand::from_bytes(&other)];
+        let program = relink(program, &strands, Linking::Deterministic);
+        assert_eq!(
+            program.call_sites()[0].binding,
+            Binding::Block(BlockId::new(1, 0))
+        );
+        assert_eq!(program.block_starts.len(), 2);
+    }
+
+    #[test]
+    fn test_bind_stochastic() {
+        let bytes = assemble("inc r0\ncall @0x0000_0003\nblock @0x0000_0003").unwrap();
+        let program = link_bytes(&[&bytes], 1.0, Linking::Stochastic);
+        assert_eq!(program.call_sites()[0].binding, Binding::Unbound);
+        let mut rng = SmallRng::from_seed([0; 32]);
+        let mut stats = LookupStats::default();
+        assert_eq!(
+            program.bind_stochastic(&mut rng, &mut stats),
+            vec![Binding::Block(BlockId::new(0, 1))]
+        );
+        assert_eq!(stats.lookups, 1);
+        assert_eq!(stats.exact, 1);
+    }
+}
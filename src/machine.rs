@@ -0,0 +1,480 @@
+use std::sync::Arc;
+
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+
+use crate::blockpattern::Patterns;
+use crate::core::{Execution, Interpreter, Processor};
+use crate::disassembler::{disassemble, Resolution};
+use crate::fuzzy::MatchPolicy;
+use crate::genome::Genome;
+use crate::linker::{link, relink, Linking, Program};
+use crate::metric::{Hamming, Metric};
+use crate::structure::{Blocks, LookupStats, Strand};
+
+/// A machine runs genomes. It is configured with consuming methods:
+///
+/// ```
+/// use evoby::Machine;
+///
+/// let machine = Machine::new().fuel(1_000).max_distance(2).seed(42);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Machine {
+    memory: Vec<u8>,
+    fuel: u64,
+    metric: Arc<dyn Metric>,
+    max_distance: u32,
+    policy: MatchPolicy,
+    patterns: Patterns,
+    linking: Linking,
+    interpreter: Interpreter,
+    seed: u64,
+}
+
+/// The result of running a genome.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Output {
+    pub execution: Execution,
+    /// The registers after execution.
+    pub registers: [u32; 4],
+    /// How fuzzy the lookups of blocks during execution were.
+    pub lookups: LookupStats,
+}
+
+impl Default for Machine {
+    fn default() -> Machine {
+        Machine {
+            memory: vec![0; 1024],
+            fuel: 10_000,
+            metric: Arc::new(Hamming),
+            max_distance: 4,
+            policy: MatchPolicy::Chance(0.9),
+            patterns: Patterns::Fixed,
+            linking: Linking::Dynamic,
+            interpreter: Interpreter::Compiled,
+            seed: 0,
+        }
+    }
+}
+
+impl Machine {
+    pub fn new() -> Machine {
+        Machine::default()
+    }
+
+    /// The size of main memory in bytes, which must be a power of two.
+    pub fn memory_size(mut self, memory_size: usize) -> Machine {
+        assert!(memory_size.is_power_of_two());
+        self.memory = vec![0; memory_size];
+        self
+    }
+
+    /// The maximum amount of instructions a run may execute.
+    pub fn fuel(mut self, fuel: u64) -> Machine {
+        self.fuel = fuel;
+        self
+    }
+
+    /// The metric for the distance between the pattern of a call and the
+    /// pattern of a block. By default this is the hamming distance.
+    pub fn metric(mut self, metric: impl Metric + 'static) -> Machine {
+        self.metric = Arc::new(metric);
+        self
+    }
+
+    /// The maximum distance between the pattern of a call and the
+    /// pattern of the block it calls.
+    pub fn max_distance(mut self, max_distance: u32) -> Machine {
+        self.max_distance = max_distance;
+        self
+    }
+
+    /// The chance that a call accepts a matching block. Candidates are
+    /// considered from the closest match to the furthest.
+    pub fn match_chance(self, match_chance: f64) -> Machine {
+        self.match_policy(MatchPolicy::Chance(match_chance))
+    }
+
+    /// How a call picks one of the matching blocks. By default it goes
+    /// through them with a match chance of 0.9.
+    pub fn match_policy(mut self, policy: MatchPolicy) -> Machine {
+        self.policy = policy.check();
+        self
+    }
+
+    /// Which bits of the patterns of calls and blocks count when matching
+    /// them. By default all 32 bits do.
+    pub fn patterns(mut self, patterns: Patterns) -> Machine {
+        self.patterns = patterns;
+        self
+    }
+
+    /// How calls are bound to the blocks they call.
+    pub fn linking(mut self, linking: Linking) -> Machine {
+        self.linking = linking;
+        self
+    }
+
+    /// How programs are executed. This doesn't change the output of a run.
+    pub fn interpreter(mut self, interpreter: Interpreter) -> Machine {
+        self.interpreter = interpreter;
+        self
+    }
+
+    /// The seed for the randomness in calls. Every run starts from this seed,
+    /// so running the same genome on the same inputs gives the same output.
+    pub fn seed(mut self, seed: u64) -> Machine {
+        self.seed = seed;
+        self
+    }
+
+    /// Link a genome into a program, binding its calls according to the
+    /// linking of this machine. A program can be run many times.
+    pub fn link(&self, genome: &Genome) -> Program {
+        let strands = genome.strand_views();
+        let blocks = self.blocks(&strands);
+        link(&strands, blocks, self.linking)
+    }
+
+    /// Link a genome again after it was edited, reusing a program it was
+    /// linked to before by this machine. The blocks of the program are
+    /// updated rather than built again, which is cheaper when a genome
+    /// changes a little at a time. The call sites are still bound and
+    /// compiled again in full.
+    pub fn relink(&self, program: Program, genome: &Genome) -> Program {
+        relink(program, &genome.strand_views(), self.linking)
+    }
+
+    /// Run a genome on inputs. Main memory is cleared, after which the inputs
+    /// are written to it as little-endian 32 bit values starting at address 0.
+    /// The first four inputs are also put in the registers.
+    pub fn run(&mut self, genome: &Genome, inputs: &[u32]) -> Output {
+        let program = self.link(genome);
+        self.run_program(&program, inputs)
+    }
+
+    /// Run a linked program on inputs, like [`Machine::run`].
+    pub fn run_program(&mut self, program: &Program, inputs: &[u32]) -> Output {
+        self.memory.fill(0);
+        let mask = self.memory.len() - 1;
+        let mut registers = [0; 4];
+        for (i, input) in inputs.iter().enumerate() {
+            if let Some(register) = registers.get_mut(i) {
+                *register = *input;
+            }
+            for (offset, byte) in input.to_le_bytes().into_iter().enumerate() {
+                self.memory[(i * 4 + offset) & mask] = byte;
+            }
+        }
+
+        let mut processor = Processor::new(
+            program,
+            &mut self.memory,
+            SmallRng::seed_from_u64(self.seed),
+        );
+        processor.set_registers(registers);
+        let execution = match self.interpreter {
+            Interpreter::Bytes => processor.run(self.fuel),
+            Interpreter::Compiled => processor.run_compiled(self.fuel),
+        };
+        Output {
+            execution,
+            registers: processor.registers(),
+            lookups: processor.lookup_stats(),
+        }
+    }
+
+    /// Main memory, as left by the last run.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    fn blocks(&self, strands: &[Strand]) -> Blocks {
+        Blocks::with_metric(
+            self.metric.clone(),
+            self.max_distance,
+            self.policy,
+            self.patterns,
+            strands,
+        )
+    }
+
+    /// Disassemble a genome, resolving calls the way this machine would.
+    pub fn disassemble(&self, genome: &Genome, resolution: Resolution) -> String {
+        let strands = genome.strand_views();
+        let blocks = self.blocks(&strands);
+        disassemble(&strands, &blocks, resolution)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::ExecutionOutcome;
+    use crate::metric::AbsoluteDifference;
+
+    use super::*;
+
+    #[test]
+    fn test_run_inputs() {
+        let genome = Genome::assemble(&["add r0 r1"]).unwrap();
+        let mut machine = Machine::new();
+        let output = machine.run(&genome, &[2, 3]);
+        assert_eq!(output.execution.outcome, ExecutionOutcome::Finished);
+        assert_eq!(output.registers, [5, 3, 0, 0]);
+    }
+
+    #[test]
+    fn test_run_inputs_in_memory() {
+        // load the sixth input into r1
+        let genome = Genome::assemble(&["value r0 20\nload r0 r1"]).unwrap();
+        let mut machine = Machine::new().memory_size(16);
+        let output = machine.run(&genome, &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(output.registers[1], 6);
+        // the memory wrapped around
+        assert_eq!(machine.memory()[..4], [5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_run_clears_memory() {
+        let genome = Genome::assemble(&["store r0 r1"]).unwrap();
+        let mut machine = Machine::new().memory_size(16);
+        machine.run(&genome, &[8, 42]);
+        assert_eq!(machine.memory()[8], 42);
+        machine.run(&genome, &[]);
+        assert_eq!(machine.memory()[8], 0);
+    }
+
+    #[test]
+    fn test_run_out_of_fuel() {
+        let genome = Genome::from_bytes(&[0x18; 100]);
+        let mut machine = Machine::new().fuel(10);
+        let output = machine.run(&genome, &[]);
+        assert_eq!(output.execution.outcome, ExecutionOutcome::OutOfFuel);
+        assert_eq!(output.registers[0], 10);
+    }
+
+    #[test]
+    fn test_run_reproducible() {
+        // a call with many candidates
+        let genome = Genome::assemble(&["
+            call @0x0000_0000
+            block @0x0000_0001
+            inc r0
+            block @0x0000_0002
+            inc r1
+            block @0x0000_0004
+            inc r2
+            "])
+        .unwrap();
+        let mut machine = Machine::new().match_chance(0.3).seed(3);
+        let output = machine.run(&genome, &[]);
+        for _ in 0..10 {
+            assert_eq!(machine.run(&genome, &[]), output);
+        }
+    }
+
+    #[test]
+    fn test_run_linking() {
+        // a single call site that is executed twice
+        let genome = Genome::assemble(&["
+            value r3 2
+            repeat r3
+            call @0x0000_0000
+            block @0x0000_0001
+            inc r0
+            block @0x0000_0002
+            inc r1
+            "])
+        .unwrap();
+        // calls never accept a match during execution, but deterministic
+        // linking binds them to the best match
+        let mut machine = Machine::new().max_distance(1).match_chance(0.0);
+        assert_eq!(machine.run(&genome, &[]).registers, [0, 0, 0, 2]);
+        let mut machine = machine.linking(Linking::Deterministic);
+        assert_eq!(machine.run(&genome, &[]).registers, [2, 0, 0, 2]);
+        // stochastic linking calls the same block each time
+        for seed in 0..10 {
+            let mut machine = Machine::new()
+                .max_distance(1)
+                .match_chance(0.5)
+                .linking(Linking::Stochastic)
+                .seed(seed);
+            let registers = machine.run(&genome, &[]).registers;
+            assert!(matches!(
+                registers,
+                [0, 0, 0, 2] | [2, 0, 0, 2] | [0, 2, 0, 2]
+            ));
+        }
+    }
+
+    #[test]
+    fn test_run_interpreter() {
+        let genome = Genome::assemble(&["
+            value r3 3
+            repeat r3
+            call @0x0000_0001
+            if r1
+            dec r0
+            block @0x0000_0001
+            add r0 r0
+            "])
+        .unwrap();
+        let machine = Machine::new().max_distance(0).match_chance(1.0);
+        let mut bytes = machine.clone().interpreter(Interpreter::Bytes);
+        let mut compiled = machine.interpreter(Interpreter::Compiled);
+        let output = bytes.run(&genome, &[1, 1]);
+        assert_eq!(output.registers[0], 7);
+        assert_eq!(compiled.run(&genome, &[1, 1]), output);
+    }
+
+    #[test]
+    fn test_run_metric() {
+        // the block patterns are one bit away from the call pattern, but
+        // far apart as integers
+        let genome = Genome::assemble(&["
+            inc r2
+            call @0x0000_0000
+            block @0x8000_0000
+            inc r0
+            block @0x0000_0001
+            inc r1
+            "])
+        .unwrap();
+        let machine = Machine::new().max_distance(1).match_chance(1.0);
+        let output = machine.clone().run(&genome, &[]);
+        assert_eq!(output.registers, [1, 0, 1, 0]);
+        let output = machine.metric(AbsoluteDifference).run(&genome, &[]);
+        assert_eq!(output.registers, [0, 1, 1, 0]);
+    }
+
+    #[test]
+    fn test_run_match_policy() {
+        let genome = Genome::assemble(&["
+            inc r2
+            call @0x0000_0000
+            block @0x0000_0003
+            inc r0
+            block @0x0000_0001
+            inc r1
+            "])
+        .unwrap();
+        // the best match is always picked, whatever the seed
+        for seed in 0..10 {
+            let mut machine = Machine::new().match_policy(MatchPolicy::Best).seed(seed);
+            assert_eq!(machine.run(&genome, &[]).registers, [0, 1, 1, 0]);
+        }
+    }
+
+    #[test]
+    fn test_run_lookups() {
+        // the call is executed three times and matches at distance 1
+        let genome = Genome::assemble(&["
+            value r3 3
+            repeat r3
+            call @0x0000_0000
+            block @0x0000_0001
+            inc r0
+            "])
+        .unwrap();
+        let mut machine = Machine::new().match_policy(MatchPolicy::Best);
+        let lookups = machine.run(&genome, &[]).lookups;
+        assert_eq!(lookups.lookups, 3);
+        assert_eq!(lookups.found, 3);
+        assert_eq!(lookups.exact, 0);
+        assert_eq!(lookups.mean_distance(), 1.0);
+        // a run of a linked program only counts its own lookups
+        let program = machine.link(&genome);
+        machine.run_program(&program, &[]);
+        assert_eq!(machine.run_program(&program, &[]).lookups, lookups);
+        // deterministic linking looks blocks up when linking
+        let mut machine = machine.linking(Linking::Deterministic);
+        assert_eq!(machine.run(&genome, &[]).lookups.lookups, 0);
+    }
+
+    #[test]
+    fn test_run_lookups_concurrently() {
+        let genome = Genome::assemble(&["
+            value r3 3
+            repeat r3
+            call @0x0000_0000
+            block @0x0000_0001
+            inc r0
+            "])
+        .unwrap();
+        let machine = Machine::new().match_policy(MatchPolicy::Best);
+        let program = machine.link(&genome);
+        let expected = machine.clone().run_program(&program, &[]).lookups;
+        // runs that share a program don't count each other's lookups
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let mut machine = machine.clone();
+                let program = &program;
+                scope.spawn(move || {
+                    for _ in 0..100 {
+                        assert_eq!(machine.run_program(program, &[]).lookups, expected);
+                    }
+                });
+            }
+        });
+        assert_eq!(program.lookup_stats().lookups, 401 * 3);
+    }
+
+    #[test]
+    fn test_run_patterns() {
+        // the called block has a single byte, so its pattern has 8 bits
+        let genome = Genome::assemble(&["inc r2\ncall @0x18ab_cdef\nblock\ninc r0"]).unwrap();
+        let machine = Machine::new().match_policy(MatchPolicy::Best);
+        assert_eq!(machine.clone().run(&genome, &[]).registers, [0, 0, 1, 0]);
+        let mut variable = machine.clone().patterns(Patterns::Variable);
+        assert_eq!(variable.run(&genome, &[]).registers, [1, 0, 1, 0]);
+        // a call that ignores the high bits of its pattern
+        let genome = Genome::assemble(&[
+            "inc r2\ncall @0x0000_5678~0xffff_0000\nblock @0xabcd_5678\ninc r0",
+        ])
+        .unwrap();
+        assert_eq!(variable.run(&genome, &[]).registers, [0, 0, 1, 0]);
+        let mut masked = machine.patterns(Patterns::Masked);
+        assert_eq!(masked.run(&genome, &[]).registers, [1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_run_program() {
+        let genome =
+            Genome::assemble(&["add r0 r1\ncall @0x0000_0001\nblock @0x0000_0001\ninc r0"])
+                .unwrap();
+        let mut machine = Machine::new().linking(Linking::Deterministic);
+        let program = machine.link(&genome);
+        assert_eq!(program.call_sites().len(), 1);
+        assert_eq!(machine.run_program(&program, &[2, 3]).registers[0], 6);
+        assert_eq!(machine.run_program(&program, &[4, 5]).registers[0], 10);
+    }
+
+    #[test]
+    fn test_relink() {
+        let mut genome =
+            Genome::assemble(&["inc r2\ncall @0x0000_0001\nblock @0x0000_0001\ninc r0"]).unwrap();
+        let mut machine = Machine::new().max_distance(0).match_chance(1.0);
+        let program = machine.link(&genome);
+        assert_eq!(machine.run_program(&program, &[]).registers[0], 1);
+        // split the called block, so it increments r1 instead
+        let end = genome.block_range(0, 1).end;
+        genome.insert_bytes(0, end - 1, &[0x19, 0x00]);
+        let program = machine.relink(program, &genome);
+        assert_eq!(
+            machine.run_program(&program, &[]),
+            machine.run(&genome, &[])
+        );
+        assert_eq!(machine.run_program(&program, &[]).registers, [0, 1, 1, 0]);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let genome = Genome::assemble(&["inc r0"]).unwrap();
+        let machine = Machine::new();
+        assert_eq!(
+            machine.disassemble(&genome, Resolution::Deterministic),
+            "; strand 0\nblock ; 0:0 @0x1800_0000\n    inc r0\n"
+        );
+    }
+}
@@ -0,0 +1,177 @@
+// metrics for the distance between two 32 bit patterns
+//
+// a fuzzy bit map finds the stored patterns that are closest to a pattern
+// according to a metric. how closeness is defined affects how robust calls
+// are against mutation.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::fuzzy::hamming_distance;
+
+/// A metric measures the distance from a pattern we look for to a stored
+/// pattern. It doesn't need to be symmetric.
+pub trait Metric: Debug + Send + Sync {
+    fn distance(&self, pattern: u32, stored: u32) -> u32;
+
+    /// Whether the distance is never less than the hamming distance. Only
+    /// then can a fuzzy bit map use its index to find candidates.
+    fn at_least_hamming(&self) -> bool {
+        false
+    }
+}
+
+impl<M: Metric + ?Sized> Metric for Arc<M> {
+    fn distance(&self, pattern: u32, stored: u32) -> u32 {
+        (**self).distance(pattern, stored)
+    }
+
+    fn at_least_hamming(&self) -> bool {
+        (**self).at_least_hamming()
+    }
+}
+
+/// The amount of bits that differ.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Hamming;
+
+impl Metric for Hamming {
+    fn distance(&self, pattern: u32, stored: u32) -> u32 {
+        hamming_distance(pattern, stored)
+    }
+
+    fn at_least_hamming(&self) -> bool {
+        true
+    }
+}
+
+/// The streak metric: 32 minus the longest run of bits that are the same,
+/// so patterns that share a long stretch of bits are close.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Streak;
+
+impl Metric for Streak {
+    fn distance(&self, pattern: u32, stored: u32) -> u32 {
+        let mut same = !(pattern ^ stored);
+        let mut streak = 0;
+        // every step shortens all runs of ones by one
+        while same != 0 {
+            same &= same << 1;
+            streak += 1;
+        }
+        32 - streak
+    }
+}
+
+/// The absolute difference between the patterns as integers, so the high
+/// bits matter far more than the low bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AbsoluteDifference;
+
+impl Metric for AbsoluteDifference {
+    fn distance(&self, pattern: u32, stored: u32) -> u32 {
+        pattern.abs_diff(stored)
+    }
+}
+
+/// The asymmetric wrap distance: how far we need to count up from the
+/// pattern to reach the stored pattern, wrapping around at the top. A
+/// pattern matches the stored patterns just above it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Wrap;
+
+impl Metric for Wrap {
+    fn distance(&self, pattern: u32, stored: u32) -> u32 {
+        stored.wrapping_sub(pattern)
+    }
+}
+
+/// Like hamming distance, but every differing bit adds its own weight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeightedBits {
+    /// The weight of each bit, from the lowest bit to the highest.
+    pub weights: [u32; 32],
+}
+
+impl WeightedBits {
+    pub fn new(weights: [u32; 32]) -> WeightedBits {
+        WeightedBits { weights }
+    }
+}
+
+impl Default for WeightedBits {
+    /// The lowest byte weighs 1 per bit, up to 4 per bit for the highest
+    /// byte.
+    fn default() -> WeightedBits {
+        WeightedBits::new(std::array::from_fn(|bit| 1 + bit as u32 / 8))
+    }
+}
+
+impl Metric for WeightedBits {
+    fn distance(&self, pattern: u32, stored: u32) -> u32 {
+        let mut differing = pattern ^ stored;
+        let mut distance = 0u32;
+        while differing != 0 {
+            let bit = differing.trailing_zeros();
+            distance = distance.saturating_add(self.weights[bit as usize]);
+            differing &= differing - 1;
+        }
+        distance
+    }
+
+    fn at_least_hamming(&self) -> bool {
+        self.weights.iter().all(|&weight| weight >= 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hamming() {
+        assert_eq!(Hamming.distance(0b1010, 0b0110), 2);
+        assert!(Hamming.at_least_hamming());
+    }
+
+    #[test]
+    fn test_streak() {
+        assert_eq!(Streak.distance(0, 0), 0);
+        assert_eq!(Streak.distance(0, u32::MAX), 32);
+        // the 16 high bits and the 15 low bits are the same
+        assert_eq!(Streak.distance(0, 0x8000), 16);
+        assert_eq!(Streak.distance(0x0101_0101, 0), 25);
+    }
+
+    #[test]
+    fn test_absolute_difference() {
+        assert_eq!(AbsoluteDifference.distance(10, 3), 7);
+        assert_eq!(AbsoluteDifference.distance(3, 10), 7);
+        assert_eq!(AbsoluteDifference.distance(0, u32::MAX), u32::MAX);
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(Wrap.distance(3, 10), 7);
+        // it's asymmetric
+        assert_eq!(Wrap.distance(10, 3), u32::MAX - 6);
+        assert_eq!(Wrap.distance(u32::MAX, 1), 2);
+    }
+
+    #[test]
+    fn test_weighted_bits() {
+        let metric = WeightedBits::default();
+        assert_eq!(metric.distance(0, 1), 1);
+        assert_eq!(metric.distance(0, 0x8000_0001), 5);
+        assert_eq!(metric.distance(0, u32::MAX), 8 * (1 + 2 + 3 + 4));
+        assert!(metric.at_least_hamming());
+        assert!(!WeightedBits::new([0; 32]).at_least_hamming());
+    }
+
+    #[test]
+    fn test_arc() {
+        let metric: Arc<dyn Metric> = Arc::new(Streak);
+        assert_eq!(metric.distance(0, 0x8000), 16);
+        assert!(!metric.at_least_hamming());
+    }
+}
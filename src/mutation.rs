@@ -0,0 +1,581 @@
+// mutation operators that change genomes
+//
+// any bytes are a valid genome, so every operator produces a genome that
+// decodes and runs. the operators know the encoding: they can change the
+// registers or opcode of an instruction, move the pattern of a call by a
+// number of bits, and split, merge or duplicate blocks.
+//
+// every operator applies at a rate, per byte, instruction, call or block,
+// and takes its randomness from the rng it is given, so a seeded rng
+// reproduces the same mutations.
+
+use std::fmt::Debug;
+use std::ops::Range;
+
+use rand::{Rng, RngCore};
+
+use crate::blockpattern::{is_pattern_byte, BlockPattern, Direction};
+use crate::genome::Genome;
+use crate::instruction::{join_value_pattern, Op};
+
+/// A mutation changes a genome in place.
+pub trait Mutation: Debug + Send + Sync {
+    fn mutate(&self, genome: &mut Genome, rng: &mut dyn RngCore);
+}
+
+/// Several mutations, applied in order.
+#[derive(Debug, Default)]
+pub struct Mutations {
+    mutations: Vec<Box<dyn Mutation>>,
+}
+
+impl Mutations {
+    pub fn new() -> Mutations {
+        Mutations::default()
+    }
+
+    /// Add a mutation to apply after the others.
+    pub fn with(mut self, mutation: impl Mutation + 'static) -> Mutations {
+        self.mutations.push(Box::new(mutation));
+        self
+    }
+}
+
+impl Mutation for Mutations {
+    fn mutate(&self, genome: &mut Genome, rng: &mut dyn RngCore) {
+        for mutation in &self.mutations {
+            mutation.mutate(genome, rng);
+        }
+    }
+}
+
+/// Replace each byte with a random byte.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointMutation {
+    /// The chance that a byte is replaced.
+    pub rate: f64,
+}
+
+impl PointMutation {
+    pub fn new(rate: f64) -> PointMutation {
+        PointMutation {
+            rate: check_rate(rate),
+        }
+    }
+}
+
+impl Mutation for PointMutation {
+    fn mutate(&self, genome: &mut Genome, rng: &mut dyn RngCore) {
+        for strand_id in 0..genome.strands().len() {
+            for index in 0..genome.strands()[strand_id].len() {
+                if rng.gen_bool(self.rate) {
+                    genome.set_byte(strand_id, index, rng.gen());
+                }
+            }
+        }
+    }
+}
+
+/// Change a nibble of an instruction: either one of its registers, or its
+/// opcode while keeping its registers. Zero bytes and pattern bytes are
+/// left alone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NibbleMutation {
+    /// The chance that an instruction is changed.
+    pub rate: f64,
+}
+
+impl NibbleMutation {
+    pub fn new(rate: f64) -> NibbleMutation {
+        NibbleMutation {
+            rate: check_rate(rate),
+        }
+    }
+}
+
+impl Mutation for NibbleMutation {
+    fn mutate(&self, genome: &mut Genome, rng: &mut dyn RngCore) {
+        for strand_id in 0..genome.strands().len() {
+            for index in 0..genome.strands()[strand_id].len() {
+                let byte = genome.strands()[strand_id][index];
+                if is_instruction(byte) && rng.gen_bool(self.rate) {
+                    genome.set_byte(strand_id, index, mutate_nibble(byte, rng));
+                }
+            }
+        }
+    }
+}
+
+fn mutate_nibble(byte: u8, rng: &mut dyn RngCore) -> u8 {
+    let change_register = rng.gen_bool(0.5);
+    match byte {
+        // call, return and value have no registers
+        0x01..=0x03 => other(byte, 0x01..0x04, rng),
+        // instructions with a register in the lowest two bits
+        0x04..=0x1F if change_register => byte & 0b1111_1100 | other(byte & 0b11, 0..4, rng),
+        0x04..=0x1F => other(byte >> 2, 1..8, rng) << 2 | byte & 0b0000_0011,
+        // instructions with two registers in the low nibble
+        _ if change_register => {
+            let shift = if rng.gen_bool(0.5) { 2 } else { 0 };
+            let register = (byte >> shift) & 0b11;
+            byte & !(0b11 << shift) | other(register, 0..4, rng) << shift
+        }
+        _ => other(byte >> 4, 2..0xF, rng) << 4 | byte & 0b0000_1111,
+    }
+}
+
+// a random value in range other than current, which is in range
+fn other(current: u8, range: Range<u8>, rng: &mut dyn RngCore) -> u8 {
+    let value = rng.gen_range(range.start..range.end - 1);
+    if value >= current {
+        value + 1
+    } else {
+        value
+    }
+}
+
+/// Insert random instructions, and delete instructions. A call or value
+/// is inserted with a random pattern, and is deleted with the pattern
+/// bytes right before it. Zero bytes are neither inserted nor deleted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InstructionIndel {
+    /// The chance that an instruction is inserted before a byte.
+    pub insert_rate: f64,
+    /// The chance that an instruction is deleted.
+    pub delete_rate: f64,
+}
+
+impl InstructionIndel {
+    pub fn new(insert_rate: f64, delete_rate: f64) -> InstructionIndel {
+        InstructionIndel {
+            insert_rate: check_rate(insert_rate),
+            delete_rate: check_rate(delete_rate),
+        }
+    }
+}
+
+impl Mutation for InstructionIndel {
+    fn mutate(&self, genome: &mut Genome, rng: &mut dyn RngCore) {
+        for strand_id in 0..genome.strands().len() {
+            let bytes = &genome.strands()[strand_id];
+            let mut mutated = Vec::with_capacity(bytes.len());
+            for &byte in bytes {
+                if rng.gen_bool(self.insert_rate) {
+                    random_instruction(&mut mutated, rng);
+                }
+                if is_instruction(byte) && rng.gen_bool(self.delete_rate) {
+                    if matches!(Op::decode(byte), Op::Call | Op::Value) {
+                        // the pattern bytes we just copied go too
+                        let patterns = mutated
+                            .iter()
+                            .rev()
+                            .take_while(|&&byte| is_pattern_byte(byte))
+                            .count();
+                        mutated.truncate(mutated.len() - patterns);
+                    }
+                    continue;
+                }
+                mutated.push(byte);
+            }
+            genome.set_strand(strand_id, mutated);
+        }
+    }
+}
+
+// add a random instruction that isn't a zero byte or pattern byte
+fn random_instruction(bytes: &mut Vec<u8>, rng: &mut dyn RngCore) {
+    let byte = rng.gen_range(0x01..0xF0);
+    match Op::decode(byte) {
+        Op::Call => bytes.extend(BlockPattern::new(rng.gen()).encode(Direction::Backward)),
+        Op::Value => {
+            let pattern = join_value_pattern(rng.gen_range(0..4), rng.gen());
+            bytes.extend(pattern.encode(Direction::Backward));
+        }
+        _ => {}
+    }
+    bytes.push(byte);
+}
+
+/// Flip bits in the patterns of calls, so that they call blocks a
+/// controlled hamming distance away. The pattern bytes right before a
+/// mutated call are replaced by pattern bytes for its new pattern, and a
+/// call without them gains them, so the instructions before it are kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CallPatternFlip {
+    /// The chance that the pattern of a call is changed.
+    pub rate: f64,
+    /// The amount of bits to flip, at most 32.
+    pub bits: u32,
+}
+
+impl CallPatternFlip {
+    pub fn new(rate: f64, bits: u32) -> CallPatternFlip {
+        assert!(bits <= 32);
+        CallPatternFlip {
+            rate: check_rate(rate),
+            bits,
+        }
+    }
+}
+
+impl Mutation for CallPatternFlip {
+    fn mutate(&self, genome: &mut Genome, rng: &mut dyn RngCore) {
+        for strand_id in 0..genome.strands().len() {
+            for block_index in (0..genome.block_count(strand_id)).rev() {
+                let range = genome.block_range(strand_id, block_index);
+                let mut block = genome.block(strand_id, block_index).to_vec();
+                // go through the calls from the end, so that rewriting a
+                // pattern doesn't move the calls we have yet to see
+                let mut index = block.len();
+                let mut changed = false;
+                while index > 0 {
+                    index -= 1;
+                    if Op::decode(block[index]) != Op::Call || !rng.gen_bool(self.rate) {
+                        continue;
+                    }
+                    let pattern = BlockPattern::decode_backward(&block, index);
+                    let flipped = BlockPattern::new(pattern.get() ^ random_bits(self.bits, rng));
+                    // replace the pattern bytes of the call, but not the
+                    // wildcards before them
+                    let run = block[..index]
+                        .iter()
+                        .rev()
+                        .take_while(|&&byte| is_pattern_byte(byte))
+                        .count();
+                    let start = index - run.min(8);
+                    block.splice(start..index, flipped.to_pattern_bytes());
+                    index = start;
+                    changed = true;
+                }
+                if changed {
+                    genome.remove_bytes(strand_id, range.clone());
+                    genome.insert_bytes(strand_id, range.start, &block);
+                }
+            }
+        }
+    }
+}
+
+// a random mask with count bits set
+fn random_bits(count: u32, rng: &mut dyn RngCore) -> u32 {
+    rand::seq::index::sample(rng, 32, count as usize)
+        .iter()
+        .fold(0, |mask, bit| mask | 1 << bit)
+}
+
+/// Insert zero bytes, which splits blocks, and remove them, which merges
+/// blocks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeparatorIndel {
+    /// The chance that a zero byte is inserted before a byte.
+    pub insert_rate: f64,
+    /// The chance that a zero byte is removed.
+    pub remove_rate: f64,
+}
+
+impl SeparatorIndel {
+    pub fn new(insert_rate: f64, remove_rate: f64) -> SeparatorIndel {
+        SeparatorIndel {
+            insert_rate: check_rate(insert_rate),
+            remove_rate: check_rate(remove_rate),
+        }
+    }
+}
+
+impl Mutation for SeparatorIndel {
+    fn mutate(&self, genome: &mut Genome, rng: &mut dyn RngCore) {
+        for strand_id in 0..genome.strands().len() {
+            let bytes = &genome.strands()[strand_id];
+            let mut mutated = Vec::with_capacity(bytes.len());
+            for &byte in bytes {
+                if rng.gen_bool(self.insert_rate) {
+                    mutated.push(0);
+                }
+                if byte == 0 && rng.gen_bool(self.remove_rate) {
+                    continue;
+                }
+                mutated.push(byte);
+            }
+            genome.set_strand(strand_id, mutated);
+        }
+    }
+}
+
+/// Duplicate blocks. The copy follows the block it is a copy of, so it has
+/// the same pattern, and calls that look from later blocks find it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockDuplication {
+    /// The chance that a block is duplicated.
+    pub rate: f64,
+}
+
+impl BlockDuplication {
+    pub fn new(rate: f64) -> BlockDuplication {
+        BlockDuplication {
+            rate: check_rate(rate),
+        }
+    }
+}
+
+impl Mutation for BlockDuplication {
+    fn mutate(&self, genome: &mut Genome, rng: &mut dyn RngCore) {
+        for strand_id in 0..genome.strands().len() {
+            // from the end, so the blocks we have yet to see don't move
+            for block_index in (0..genome.block_count(strand_id)).rev() {
+                if !rng.gen_bool(self.rate) {
+                    continue;
+                }
+                let range = genome.block_range(strand_id, block_index);
+                let mut copy = vec![0];
+                copy.extend_from_slice(genome.block(strand_id, block_index));
+                genome.insert_bytes(strand_id, range.end, &copy);
+            }
+        }
+    }
+}
+
+fn check_rate(rate: f64) -> f64 {
+    assert!((0.0..=1.0).contains(&rate));
+    rate
+}
+
+// whether a byte is an instruction, rather than a zero byte or a pattern byte
+fn is_instruction(byte: u8) -> bool {
+    !matches!(Op::decode(byte), Op::Block | Op::Pattern(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use crate::machine::Machine;
+
+    use super::*;
+
+    fn genome() -> Genome {
+        Genome::assemble(&[
+            "inc r0\ncall @0x0000_0003\nblock @0x0000_0003\nadd r0 r1\nvalue r2 0x1234",
+            "push r1\nblock\nreturn",
+        ])
+        .unwrap()
+    }
+
+    fn mutated(mutation: &impl Mutation, seed: u8) -> Genome {
+        let mut genome = genome();
+        mutation.mutate(&mut genome, &mut SmallRng::from_seed([seed; 32]));
+        genome
+    }
+
+    fn count(genome: &Genome, predicate: impl Fn(u8) -> bool) -> usize {
+        genome
+            .strands()
+            .iter()
+            .flatten()
+            .filter(|&&byte| predicate(byte))
+            .count()
+    }
+
+    fn calls(genome: &Genome) -> Vec<BlockPattern> {
+        let mut patterns = Vec::new();
+        for strand in genome.strands() {
+            for (index, &byte) in strand.iter().enumerate() {
+                if Op::decode(byte) == Op::Call {
+                    patterns.push(BlockPattern::decode_backward(strand, index));
+                }
+            }
+        }
+        patterns
+    }
+
+    #[test]
+    fn test_rate_zero() {
+        let mutations = Mutations::new()
+            .with(PointMutation::new(0.0))
+            .with(NibbleMutation::new(0.0))
+            .with(InstructionIndel::new(0.0, 0.0))
+            .with(CallPatternFlip::new(0.0, 3))
+            .with(SeparatorIndel::new(0.0, 0.0))
+            .with(BlockDuplication::new(0.0));
+        assert_eq!(mutated(&mutations, 0), genome());
+    }
+
+    #[test]
+    fn test_same_seed() {
+        let mutations = Mutations::new()
+            .with(PointMutation::new(0.1))
+            .with(InstructionIndel::new(0.1, 0.1));
+        assert_eq!(mutated(&mutations, 1), mutated(&mutations, 1));
+        assert_ne!(mutated(&mutations, 1), mutated(&mutations, 2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_rate_out_of_range() {
+        PointMutation::new(1.5);
+    }
+
+    #[test]
+    fn test_point_mutation() {
+        let genome = mutated(&PointMutation::new(1.0), 0);
+        assert_eq!(genome.len(), self::genome().len());
+        assert_ne!(genome, self::genome());
+    }
+
+    #[test]
+    fn test_nibble_mutation() {
+        let original = genome();
+        let genome = mutated(&NibbleMutation::new(1.0), 0);
+        for (strand, original) in genome.strands().iter().zip(original.strands()) {
+            assert_eq!(strand.len(), original.len());
+            for (&byte, &original) in strand.iter().zip(original) {
+                if is_instruction(original) {
+                    assert!(is_instruction(byte));
+                    assert_ne!(byte, original);
+                } else {
+                    assert_eq!(byte, original);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_mutate_nibble() {
+        let mut rng = SmallRng::from_seed([0; 32]);
+        for byte in (0x01..0xF0).filter(|&byte| is_instruction(byte)) {
+            for _ in 0..16 {
+                let mutated = mutate_nibble(byte, &mut rng);
+                assert!(is_instruction(mutated));
+                // exactly one of the opcode or the registers changes
+                let changed = byte ^ mutated;
+                match byte {
+                    0x01..=0x03 => assert!(mutated <= 0x03),
+                    0x04..=0x1F => assert!(changed & 0b11 == 0 || changed >> 2 == 0),
+                    _ => assert!([0b0000_1100, 0b0000_0011, 0b1111_0000]
+                        .iter()
+                        .any(|&mask| changed & !mask == 0)),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_instruction_indel() {
+        let original = genome();
+        let deleted = mutated(&InstructionIndel::new(0.0, 1.0), 0);
+        // only zero bytes remain
+        assert_eq!(count(&deleted, |byte| byte != 0), 0);
+        assert_eq!(
+            count(&deleted, |byte| byte == 0),
+            count(&original, |byte| byte == 0)
+        );
+        let inserted = mutated(&InstructionIndel::new(0.5, 0.0), 0);
+        assert!(inserted.len() > original.len());
+        assert_eq!(
+            count(&inserted, |byte| byte == 0),
+            count(&original, |byte| byte == 0)
+        );
+    }
+
+    #[test]
+    fn test_call_pattern_flip() {
+        let genome = mutated(&CallPatternFlip::new(1.0, 3), 0);
+        let original = calls(&self::genome());
+        let flipped = calls(&genome);
+        assert_eq!(flipped.len(), original.len());
+        for (flipped, original) in flipped.iter().zip(&original) {
+            assert_eq!((flipped.get() ^ original.get()).count_ones(), 3);
+        }
+        // the call already had pattern bytes, which are replaced
+        assert_eq!(genome.len(), self::genome().len());
+    }
+
+    #[test]
+    fn test_call_pattern_flip_keeps_instructions() {
+        let instructions = |genome: &Genome| -> Vec<u8> {
+            genome.strands()[0]
+                .iter()
+                .copied()
+                .filter(|&byte| !is_pattern_byte(byte))
+                .collect()
+        };
+        let original = Genome::assemble(&["inc r0\ninc r1\ncall\ncall"]).unwrap();
+        assert_eq!(original.strands()[0], [0x18, 0x19, 0x01, 0x01]);
+        for seed in 0..8 {
+            let mut genome = original.clone();
+            let mut rng = SmallRng::from_seed([seed; 32]);
+            CallPatternFlip::new(1.0, 1).mutate(&mut genome, &mut rng);
+            assert_eq!(instructions(&genome), instructions(&original));
+            // each call gained pattern bytes
+            assert_eq!(genome.len(), original.len() + 16);
+            let original_calls = calls(&original);
+            for (flipped, original) in calls(&genome).iter().zip(&original_calls) {
+                assert_eq!((flipped.get() ^ original.get()).count_ones(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_separator_indel() {
+        let original = genome();
+        let merged = mutated(&SeparatorIndel::new(0.0, 1.0), 0);
+        assert_eq!(merged.block_count(0), 1);
+        assert_eq!(merged.block_count(1), 1);
+        let split = mutated(&SeparatorIndel::new(1.0, 0.0), 0);
+        assert_eq!(split.len(), 2 * original.len());
+        assert_eq!(split.block_count(1), 2 * original.block_count(1) + 1);
+    }
+
+    #[test]
+    fn test_block_duplication() {
+        let genome = mutated(&BlockDuplication::new(1.0), 0);
+        assert_eq!(genome.block_count(0), 4);
+        assert_eq!(genome.block(0, 0), genome.block(0, 1));
+        assert_eq!(genome.block(0, 2), genome.block(0, 3));
+        assert_eq!(genome.block(1, 1), &[0x11]);
+        assert_eq!(genome.block(1, 3), &[0x02]);
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync + 'static>() {}
+        assert_send_sync::<Mutations>();
+    }
+
+    fn mutation() -> impl Strategy<Value = Mutations> {
+        let rate = 0.0..=0.5;
+        (
+            rate.clone(),
+            rate.clone(),
+            (rate.clone(), rate.clone()),
+            (rate.clone(), 0..=32u32),
+            (rate.clone(), rate.clone()),
+            rate,
+        )
+            .prop_map(|(point, nibble, indel, flip, separator, duplication)| {
+                Mutations::new()
+                    .with(PointMutation::new(point))
+                    .with(NibbleMutation::new(nibble))
+                    .with(InstructionIndel::new(indel.0, indel.1))
+                    .with(CallPatternFlip::new(flip.0, flip.1))
+                    .with(SeparatorIndel::new(separator.0, separator.1))
+                    .with(BlockDuplication::new(duplication))
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn test_mutated_genomes_run(
+            strands in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..32), 1..4),
+            mutation in mutation(),
+            seed in any::<u8>(),
+        ) {
+            let mut genome = Genome::new(strands);
+            mutation.mutate(&mut genome, &mut SmallRng::from_seed([seed; 32]));
+            // the blocks were kept up to date through the edits
+            prop_assert_eq!(&genome, &Genome::new(genome.strands().to_vec()));
+            Machine::new().fuel(1000).run(&genome, &[1, 2]);
+        }
+    }
+}
@@ -1,24 +1,173 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
 use rand::Rng;
 
 use crate::blockid::BlockId;
-use crate::blockpattern::BlockPattern;
-use crate::fuzzy::FuzzyBitMap;
+use crate::blockpattern::{BlockPattern, Patterns};
+use crate::fuzzy::{FuzzyBitMap, Lookup, Masked, MatchPolicy};
+use crate::instruction::{EncodeError, Instruction};
+use crate::metric::Metric;
 
 pub(crate) struct Strand<'a> {
     blocks: Vec<Block<'a>>,
 }
 
+// the lookup of a block index beyond all blocks
+const NO_LOOKUP: Lookup<'static, BlockId> = Lookup {
+    found: None,
+    considered: 0,
+};
+
+#[derive(Debug)]
 pub(crate) struct Blocks {
-    fuzzy_bit_map: FuzzyBitMap<BlockId>,
+    fuzzy_bit_map: FuzzyBitMap<BlockId, Arc<dyn Metric>>,
+    patterns: Patterns,
+    // for each block index, the index in the fuzzy bit map where
+    // the blocks with that index start
+    block_index_starts: Vec<usize>,
+    // the amount of blocks in each strand
+    block_counts: Vec<usize>,
+    // blocks are shared during execution, so the counters are atomic
+    counters: LookupCounters,
+}
+
+#[derive(Debug, Default)]
+struct LookupCounters {
+    lookups: AtomicU64,
+    found: AtomicU64,
+    exact: AtomicU64,
+    total_distance: AtomicU64,
+    total_rank: AtomicU64,
+    total_considered: AtomicU64,
+}
+
+/// How fuzzy the lookups of blocks by pattern were.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LookupStats {
+    pub lookups: u64,
+    /// The amount of lookups that found a block.
+    pub found: u64,
+    /// The amount of lookups that found a block at distance 0.
+    pub exact: u64,
+    /// The sum of the distances of the blocks found.
+    pub total_distance: u64,
+    /// The sum of the ranks of the blocks found.
+    pub total_rank: u64,
+    /// The sum of the amount of matches each lookup considered.
+    pub total_considered: u64,
+}
+
+impl LookupStats {
+    /// The mean distance of the blocks found.
+    pub fn mean_distance(&self) -> f64 {
+        mean(self.total_distance, self.found)
+    }
+
+    /// The mean rank of the blocks found.
+    pub fn mean_rank(&self) -> f64 {
+        mean(self.total_rank, self.found)
+    }
+
+    /// The mean amount of matches considered per lookup.
+    pub fn mean_considered(&self) -> f64 {
+        mean(self.total_considered, self.lookups)
+    }
+
+    // count a lookup
+    pub(crate) fn record(&mut self, lookup: &Lookup<BlockId>) {
+        self.lookups += 1;
+        self.total_considered += lookup.considered as u64;
+        if let Some(found) = &lookup.found {
+            self.found += 1;
+            self.exact += (found.distance == 0) as u64;
+            self.total_distance += found.distance as u64;
+            self.total_rank += found.rank as u64;
+        }
+    }
+
+    /// The lookups since earlier stats were taken.
+    pub fn since(&self, earlier: &LookupStats) -> LookupStats {
+        LookupStats {
+            lookups: self.lookups - earlier.lookups,
+            found: self.found - earlier.found,
+            exact: self.exact - earlier.exact,
+            total_distance: self.total_distance - earlier.total_distance,
+            total_rank: self.total_rank - earlier.total_rank,
+            total_considered: self.total_considered - earlier.total_considered,
+        }
+    }
+}
+
+// the bits of a pattern that count when matching
+fn masked(pattern: BlockPattern, patterns: Patterns) -> Masked {
+    Masked::new(pattern.get(), pattern.mask(patterns))
+}
+
+fn mean(total: u64, count: u64) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        total as f64 / count as f64
+    }
+}
+
+impl LookupCounters {
+    fn record(&self, lookup: &Lookup<BlockId>) {
+        let mut stats = LookupStats::default();
+        stats.record(lookup);
+        let add = |counter: &AtomicU64, value: u64| {
+            counter.fetch_add(value, Ordering::Relaxed);
+        };
+        add(&self.lookups, stats.lookups);
+        add(&self.found, stats.found);
+        add(&self.exact, stats.exact);
+        add(&self.total_distance, stats.total_distance);
+        add(&self.total_rank, stats.total_rank);
+        add(&self.total_considered, stats.total_considered);
+    }
+
+    fn stats(&self) -> LookupStats {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        LookupStats {
+            lookups: get(&self.lookups),
+            found: get(&self.found),
+            exact: get(&self.exact),
+            total_distance: get(&self.total_distance),
+            total_rank: get(&self.total_rank),
+            total_considered: get(&self.total_considered),
+        }
+    }
+}
+
+/// builds the bytes of a strand, block by block
+#[derive(Debug, Default)]
+pub struct StrandBuilder {
+    bytes: Vec<u8>,
+    started: bool,
 }
 
 #[derive(Debug)]
-struct Block<'a> {
+pub(crate) struct Block<'a> {
     pattern: BlockPattern,
     slice: &'a [u8],
 }
 
+impl<'a> Block<'a> {
+    // the pattern that identifies the block
+    pub(crate) fn pattern(&self) -> &BlockPattern {
+        &self.pattern
+    }
+
+    pub(crate) fn slice(&self) -> &'a [u8] {
+        self.slice
+    }
+}
+
 impl<'a> Strand<'a> {
+    // genomes know their block ranges, so this is only used by tests
+    #[cfg(test)]
     pub(crate) fn from_bytes(data: &'a [u8]) -> Strand<'a> {
         // split data by zero byte into slices
         // use the slice to construct the BlockIdentifier
@@ -34,45 +183,276 @@ impl<'a> Strand<'a> {
         }
         Strand { blocks }
     }
+
+    // a strand of which the blocks are already known, as ranges in data
+    pub(crate) fn from_block_ranges(data: &'a [u8], ranges: &[Range<usize>]) -> Strand<'a> {
+        let blocks = ranges
+            .iter()
+            .map(|range| {
+                let slice = &data[range.clone()];
+                Block {
+                    pattern: BlockPattern::decode_forward(slice, 0),
+                    slice,
+                }
+            })
+            .collect();
+        Strand { blocks }
+    }
+
+    pub(crate) fn blocks(&self) -> &[Block<'a>] {
+        &self.blocks
+    }
+
+    pub(crate) fn block_slices(&self) -> impl Iterator<Item = &'a [u8]> + '_ {
+        self.blocks.iter().map(|block| block.slice)
+    }
+}
+
+impl StrandBuilder {
+    pub fn new() -> StrandBuilder {
+        StrandBuilder::default()
+    }
+
+    /// start a new block, identified by the given pattern
+    pub fn block(self, pattern: BlockPattern) -> StrandBuilder {
+        let mut builder = self.empty_block();
+        builder.bytes.extend(pattern.to_pattern_bytes());
+        builder
+    }
+
+    /// start a new block without an identifying pattern. the first block
+    /// is started implicitly, so this only separates blocks.
+    pub fn empty_block(mut self) -> StrandBuilder {
+        if self.started {
+            self.bytes.push(0);
+        }
+        self.started = true;
+        self
+    }
+
+    /// add an instruction to the current block, or fail if it has no
+    /// encoding
+    pub fn instruction(mut self, instruction: Instruction) -> Result<StrandBuilder, EncodeError> {
+        instruction.encode(&mut self.bytes)?;
+        self.started = true;
+        Ok(self)
+    }
+
+    /// add just the byte of an instruction to the current block. a bare call or
+    /// value takes its pattern from whatever bytes are before it.
+    pub fn bare(mut self, instruction: Instruction) -> Result<StrandBuilder, EncodeError> {
+        self.bytes.push(instruction.bytecode()?);
+        self.started = true;
+        Ok(self)
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        self.bytes
+    }
 }
 
 impl Blocks {
+    // blocks matched by hamming distance
+    #[cfg(test)]
     pub(crate) fn new(max_distance: u32, match_chance: f64, strands: &[Strand]) -> Blocks {
-        let mut fuzzy_bit_map = FuzzyBitMap::new(max_distance, match_chance);
+        Blocks::with_metric(
+            Arc::new(crate::metric::Hamming),
+            max_distance,
+            MatchPolicy::Chance(match_chance),
+            Patterns::Fixed,
+            strands,
+        )
+    }
+
+    // the blocks of strands, matched by a metric. calls match the blocks
+    // with a pattern within max_distance of theirs, and the policy picks
+    // which of those a call goes to; it comes from Machine::match_policy,
+    // which is how it's configured. patterns tells which bits of the
+    // patterns count.
+    pub(crate) fn with_metric(
+        metric: Arc<dyn Metric>,
+        max_distance: u32,
+        policy: MatchPolicy,
+        patterns: Patterns,
+        strands: &[Strand],
+    ) -> Blocks {
+        let mut fuzzy_bit_map =
+            FuzzyBitMap::with_metric(metric, max_distance, 1.0).with_policy(policy);
+        let mut block_index_starts = Vec::new();
         // walk through each strand block by block, and insert them at the same level in fuzzy map
         let mut block_index = 0;
+        let mut fuzzy_index = 0;
         loop {
             let mut exhausted = true;
+            block_index_starts.push(fuzzy_index);
             for (strand_id, strand) in strands.iter().enumerate() {
                 if block_index >= strand.blocks.len() {
                     continue;
                 }
                 exhausted = false;
                 let block = &strand.blocks[block_index];
-                fuzzy_bit_map.insert(block.pattern.get(), BlockId::new(strand_id, block_index));
+                fuzzy_bit_map.insert(
+                    masked(block.pattern, patterns),
+                    BlockId::new(strand_id, block_index),
+                );
+                fuzzy_index += 1;
             }
             if exhausted {
                 break;
             }
             block_index += 1;
         }
-        Self { fuzzy_bit_map }
+        Self {
+            fuzzy_bit_map,
+            patterns,
+            block_index_starts,
+            block_counts: strands.iter().map(|strand| strand.blocks.len()).collect(),
+            counters: LookupCounters::default(),
+        }
     }
 
+    // update the blocks to those of edited strands, which is cheaper than
+    // building them again when few blocks changed. blocks are matched up by
+    // strand and block index: a block with a new pattern is re-keyed, and
+    // the blocks beyond the old or new end of a strand are removed or
+    // inserted, all at once. a block inserted at the start of a strand
+    // moves the patterns of all blocks after it, so those are all re-keyed.
+    // strands that are gone are emptied.
+    pub(crate) fn update(&mut self, strands: &[Strand]) {
+        let old_counts = std::mem::take(&mut self.block_counts);
+        let new_counts = strands
+            .iter()
+            .map(|strand| strand.blocks.len())
+            .collect::<Vec<_>>();
+        let strand_count = old_counts.len().max(new_counts.len());
+        let levels = old_counts
+            .iter()
+            .chain(&new_counts)
+            .max()
+            .copied()
+            .unwrap_or(0);
+
+        // walk through the blocks in the order of the fuzzy bit map, both as
+        // they were and as they will be
+        let mut removed = Vec::new();
+        let mut inserted = Vec::new();
+        let mut old_index = 0;
+        let mut new_index = 0;
+        for block_index in 0..levels {
+            for strand_id in 0..strand_count {
+                let old = old_counts.get(strand_id).is_some_and(|&n| n > block_index);
+                let block = strands
+                    .get(strand_id)
+                    .and_then(|strand| strand.blocks.get(block_index));
+                match block {
+                    Some(block) => {
+                        let pattern = masked(block.pattern, self.patterns);
+                        if !old {
+                            let block_id = BlockId::new(strand_id, block_index);
+                            inserted.push((new_index, pattern, block_id));
+                        } else if self.fuzzy_bit_map.entry(old_index).map(|entry| entry.0)
+                            != Some(pattern)
+                        {
+                            self.fuzzy_bit_map.set_pattern(old_index, pattern);
+                        }
+                        new_index += 1;
+                    }
+                    None if old => removed.push(old_index),
+                    None => {}
+                }
+                old_index += old as usize;
+            }
+        }
+        self.fuzzy_bit_map.remove_many(removed);
+        self.fuzzy_bit_map.insert_many(inserted);
+
+        let mut start = 0;
+        let new_levels = new_counts.iter().max().copied().unwrap_or(0);
+        self.block_index_starts = (0..=new_levels)
+            .map(|block_index| {
+                let level_start = start;
+                start += new_counts.iter().filter(|&&n| n > block_index).count();
+                level_start
+            })
+            .collect();
+        self.block_counts = new_counts;
+    }
+
+    // the pattern of the call at index in a block
+    pub(crate) fn call_pattern(&self, slice: &[u8], index: usize) -> BlockPattern {
+        match self.patterns {
+            Patterns::Masked => BlockPattern::decode_backward_masked(slice, index),
+            _ => BlockPattern::decode_backward(slice, index),
+        }
+    }
+
+    // the distance from a pattern to the pattern of a block
+    pub(crate) fn distance(&self, pattern: BlockPattern, block_pattern: BlockPattern) -> u32 {
+        self.fuzzy_bit_map.distance(
+            masked(pattern, self.patterns),
+            masked(block_pattern, self.patterns),
+        )
+    }
+
+    // look up a block by pattern, considering only blocks with
+    // a block index of at least block_index
     pub(crate) fn lookup(
         &self,
         pattern: BlockPattern,
         block_index: usize,
         rng: &mut impl Rng,
     ) -> Option<&BlockId> {
-        self.fuzzy_bit_map.get(pattern.get(), block_index, rng)
+        self.lookup_match(pattern, block_index, rng)
+            .found
+            .map(|found| found.value)
+    }
+
+    // look up a block like lookup, and tell how it was found
+    pub(crate) fn lookup_match(
+        &self,
+        pattern: BlockPattern,
+        block_index: usize,
+        rng: &mut impl Rng,
+    ) -> Lookup<'_, BlockId> {
+        let lookup = match self.block_index_starts.get(block_index) {
+            Some(&index) => self
+                .fuzzy_bit_map
+                .lookup(masked(pattern, self.patterns), index, rng),
+            None => NO_LOOKUP,
+        };
+        self.counters.record(&lookup);
+        lookup
+    }
+
+    // look up the best matching block by pattern, without randomness
+    pub(crate) fn lookup_best(
+        &self,
+        pattern: BlockPattern,
+        block_index: usize,
+    ) -> Option<&BlockId> {
+        let lookup = match self.block_index_starts.get(block_index) {
+            Some(&index) => self
+                .fuzzy_bit_map
+                .lookup_best(masked(pattern, self.patterns), index),
+            None => NO_LOOKUP,
+        };
+        self.counters.record(&lookup);
+        lookup.found.map(|found| found.value)
+    }
+
+    // the counters of all lookups so far
+    pub(crate) fn lookup_stats(&self) -> LookupStats {
+        self.counters.stats()
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
     use rand::{rngs::SmallRng, SeedableRng};
 
+    use crate::instruction::{BlockRef, RegisterId};
+
     use super::*;
 
     #[test]
@@ -82,4 +462,185 @@ mod tests {
         let mut rng = SmallRng::from_seed([0; 32]);
         assert_eq!(blocks.lookup(BlockPattern::new(0b1111), 0, &mut rng), None);
     }
+
+    #[test]
+    fn test_lookup_multiple_strands() {
+        let strands = vec![
+            Strand::from_bytes(&[0x11, 0, 0x12, 0, 0x13]),
+            Strand::from_bytes(&[0x21, 0, 0x22]),
+        ];
+        let blocks = Blocks::new(0, 1.0, &strands);
+        let mut rng = SmallRng::from_seed([0; 32]);
+        assert_eq!(
+            blocks.lookup(BlockPattern::new(0x2200_0000), 0, &mut rng),
+            Some(&BlockId::new(1, 1))
+        );
+        assert_eq!(
+            blocks.lookup(BlockPattern::new(0x2200_0000), 1, &mut rng),
+            Some(&BlockId::new(1, 1))
+        );
+        assert_eq!(
+            blocks.lookup(BlockPattern::new(0x1300_0000), 2, &mut rng),
+            Some(&BlockId::new(0, 2))
+        );
+        // we don't look at blocks with a lower block index
+        assert_eq!(
+            blocks.lookup(BlockPattern::new(0x2100_0000), 1, &mut rng),
+            None
+        );
+        // nor beyond the last block index
+        assert_eq!(
+            blocks.lookup(BlockPattern::new(0x1300_0000), 3, &mut rng),
+            None
+        );
+    }
+
+    #[test]
+    fn test_strand_builder() {
+        let bytes = StrandBuilder::new()
+            .block(BlockPattern::new(0x1234_5678))
+            .instruction(Instruction::Inc(RegisterId(0)))
+            .unwrap()
+            .instruction(Instruction::Call(BlockRef::Pattern(BlockPattern::new(
+                0x8765_4321,
+            ))))
+            .unwrap()
+            .block(BlockPattern::new(0x8765_4321))
+            .instruction(Instruction::Inc(RegisterId(1)))
+            .unwrap()
+            .build();
+        let strands = vec![Strand::from_bytes(&bytes)];
+        assert_eq!(strands[0].blocks.len(), 2);
+        assert_eq!(strands[0].blocks[0].pattern, BlockPattern::new(0x1234_5678));
+        assert_eq!(strands[0].blocks[1].pattern, BlockPattern::new(0x8765_4321));
+        let blocks = Blocks::new(0, 1.0, &strands);
+        let mut rng = SmallRng::from_seed([0; 32]);
+        assert_eq!(
+            blocks.lookup(BlockPattern::new(0x8765_4321), 0, &mut rng),
+            Some(&BlockId::new(0, 1))
+        );
+    }
+
+    #[test]
+    fn test_strand_builder_invalid() {
+        let builder = StrandBuilder::new().instruction(Instruction::Inc(RegisterId(0)));
+        let error = builder
+            .unwrap()
+            .instruction(Instruction::Inc(RegisterId(4)))
+            .unwrap_err();
+        assert_eq!(error, EncodeError::InvalidRegister(4));
+        let error = StrandBuilder::new()
+            .bare(Instruction::Call(BlockRef::Id(BlockId::new(0, 0))))
+            .unwrap_err();
+        assert_eq!(error, EncodeError::UnresolvedCall);
+    }
+
+    #[test]
+    fn test_strand_builder_without_block() {
+        let bytes = StrandBuilder::new()
+            .instruction(Instruction::Inc(RegisterId(0)))
+            .unwrap()
+            .block(BlockPattern::new(0x1234_5678))
+            .build();
+        assert_eq!(
+            bytes,
+            vec![0x18, 0, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8]
+        );
+    }
+
+    #[test]
+    fn test_lookup_stats() {
+        let strands = vec![Strand::from_bytes(&[0xF1, 0x00, 0xF3])];
+        let blocks = Blocks::new(1, 1.0, &strands);
+        let mut rng = SmallRng::from_seed([0; 32]);
+        // exact, distance 1, and no match
+        blocks.lookup(BlockPattern::new(0x1000_0000), 0, &mut rng);
+        blocks.lookup(BlockPattern::new(0x3000_0001), 1, &mut rng);
+        blocks.lookup_best(BlockPattern::new(0x0fff_ffff), 0);
+        let stats = blocks.lookup_stats();
+        assert_eq!(
+            stats,
+            LookupStats {
+                lookups: 3,
+                found: 2,
+                exact: 1,
+                total_distance: 1,
+                total_rank: 0,
+                total_considered: 2,
+            }
+        );
+        assert_eq!(stats.mean_distance(), 0.5);
+        assert_eq!(stats.since(&stats), LookupStats::default());
+    }
+
+    // the blocks in the order of the fuzzy bit map
+    fn entries(blocks: &Blocks) -> Vec<(Masked, BlockId)> {
+        blocks
+            .fuzzy_bit_map
+            .iter()
+            .map(|(pattern, block_id)| (pattern, block_id.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_update_split_block() {
+        let strands = vec![
+            Strand::from_bytes(&[0xF1, 0x18, 0x00, 0xF2]),
+            Strand::from_bytes(&[0xF3]),
+        ];
+        let mut blocks = Blocks::new(0, 1.0, &strands);
+        // split the first block of the first strand
+        let strands = vec![
+            Strand::from_bytes(&[0xF1, 0x00, 0x18, 0x00, 0xF2]),
+            Strand::from_bytes(&[0xF3]),
+        ];
+        blocks.update(&strands);
+        assert_eq!(blocks.block_index_starts, vec![0, 2, 3, 4]);
+        let mut rng = SmallRng::from_seed([0; 32]);
+        assert_eq!(
+            blocks.lookup(BlockPattern::new(0x2000_0000), 1, &mut rng),
+            Some(&BlockId::new(0, 2))
+        );
+        assert_eq!(entries(&blocks), entries(&Blocks::new(0, 1.0, &strands)));
+    }
+
+    #[test]
+    fn test_update_remove_strand() {
+        let strands = vec![
+            Strand::from_bytes(&[0xF1, 0x00, 0xF2]),
+            Strand::from_bytes(&[0xF3]),
+        ];
+        let mut blocks = Blocks::new(0, 1.0, &strands);
+        let strands = vec![Strand::from_bytes(&[0xF3])];
+        blocks.update(&strands);
+        assert_eq!(blocks.block_index_starts, vec![0, 1]);
+        assert_eq!(blocks.block_counts, vec![1]);
+        assert_eq!(
+            entries(&blocks),
+            vec![(Masked::from(0x3000_0000), BlockId::new(0, 0))]
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_update_like_new(
+            before in prop::collection::vec(
+                prop::collection::vec(prop_oneof![Just(0u8), 0xF0..=0xFFu8, any::<u8>()], 0..16),
+                0..4,
+            ),
+            after in prop::collection::vec(
+                prop::collection::vec(prop_oneof![Just(0u8), 0xF0..=0xFFu8, any::<u8>()], 0..16),
+                0..4,
+            ),
+        ) {
+            let strands = before.iter().map(|bytes| Strand::from_bytes(bytes)).collect::<Vec<_>>();
+            let mut blocks = Blocks::new(2, 1.0, &strands);
+            let strands = after.iter().map(|bytes| Strand::from_bytes(bytes)).collect::<Vec<_>>();
+            blocks.update(&strands);
+            let expected = Blocks::new(2, 1.0, &strands);
+            prop_assert_eq!(entries(&blocks), entries(&expected));
+            prop_assert_eq!(&blocks.block_index_starts, &expected.block_index_starts);
+            prop_assert_eq!(&blocks.block_counts, &expected.block_counts);
+        }
+    }
 }